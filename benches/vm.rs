@@ -0,0 +1,50 @@
+// `cargo bench` times the VM on arithmetic, `cargo bench --features nan-boxing` with the
+// packed values. There are no loops or functions yet, so the scripts are long straight
+// lines of code instead of fib(30).
+use std::time::{Duration, Instant};
+
+use lox::{compiler, parser::Parser, scanner::Scanner, vm::Vm};
+
+fn script(steps: usize) -> (&'static str, String) {
+    let mut fib = "var a = 0; var b = 1; var t;\n".to_owned();
+    for _ in 0..steps {
+        fib.push_str("t = (a + b) % 1000000007; a = b; b = t;\n");
+    }
+    ("fib", fib)
+}
+
+fn polynomial(steps: usize) -> (&'static str, String) {
+    let mut poly = "var x = 0.5; var y = 0;\n".to_owned();
+    for _ in 0..steps {
+        poly.push_str("y = ((3 * x - 2) * x + 1) * x - y / 4; x = x * 0.999 + 0.001 > 1 ? 0.5 : x + 0.001;\n");
+    }
+    ("polynomial", poly)
+}
+
+fn main() {
+    let representation = if cfg!(feature = "nan-boxing") { "nan-boxing" } else { "enum" };
+    println!("values: {representation}, {} bytes", std::mem::size_of::<lox::value::VmValue>());
+
+    for (name, source) in [script(5000), polynomial(2000)] {
+        let mut parser = Parser::new(Scanner::new(source).scan());
+        let stmts = parser.parse_program().expect("benchmark script parses");
+        let chunk = compiler::compile(&parser.ast, &stmts).expect("benchmark script compiles");
+
+        let mut vm = Vm::default();
+        let mut runs = 0;
+        let mut best = Duration::MAX;
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            let run = Instant::now();
+            vm.run(&chunk).expect("benchmark script runs");
+            best = best.min(run.elapsed());
+            runs += 1;
+        }
+        println!(
+            "{name:<12} {:>8} bytes of code  best {:>10.3?}  {:>6.2} ns/byte  ({runs} runs)",
+            chunk.code.len(),
+            best,
+            best.as_nanos() as f64 / chunk.code.len() as f64
+        );
+    }
+}
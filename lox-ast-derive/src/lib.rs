@@ -0,0 +1,512 @@
+// Generates an arena-allocated AST from an enum of node variants, see `ast`.
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Field, Fields, ItemEnum, Path, Type, Variant, Visibility};
+
+// how a field refers to other nodes of a tree
+enum Child {
+    One,
+    Optional,
+    Many,
+}
+
+impl Child {
+    fn of(ty: &Type, id: &Ident) -> Option<Child> {
+        let ty = quote!(#ty).to_string().replace(' ', "");
+        if *id == ty {
+            Some(Child::One)
+        } else if ty == format!("Option<{id}>") {
+            Some(Child::Optional)
+        } else if ty == format!("Vec<{id}>") {
+            Some(Child::Many)
+        } else {
+            None
+        }
+    }
+}
+
+struct Node<'a> {
+    name: &'a Ident,
+    // e.g. `BinaryExpr`
+    struct_name: Ident,
+    // e.g. `binary`
+    snake_name: Ident,
+    fields: Vec<&'a Field>,
+}
+
+impl<'a> Node<'a> {
+    fn new(variant: &'a Variant, base: &Ident) -> syn::Result<Self> {
+        let fields = match &variant.fields {
+            Fields::Named(fields) => fields.named.iter().collect(),
+            Fields::Unit => vec![],
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new_spanned(variant, "ast nodes must have named fields"));
+            }
+        };
+
+        Ok(Self {
+            name: &variant.ident,
+            struct_name: format_ident!("{}{}", variant.ident, base),
+            snake_name: format_ident!("{}", snake_case(&variant.ident.to_string())),
+            fields,
+        })
+    }
+
+    fn children(&self, id: &Ident) -> Vec<(&Ident, Child)> {
+        self.fields
+            .iter()
+            .filter_map(|f| Child::of(&f.ty, id).map(|c| (f.ident.as_ref().unwrap(), c)))
+            .collect()
+    }
+
+    // the children which are nodes of other trees
+    fn foreign_children<'f>(&self, foreign: &'f [Foreign]) -> Vec<(&Ident, Child, &'f Foreign)> {
+        foreign
+            .iter()
+            .flat_map(|f| self.children(&f.id).into_iter().map(move |(field, child)| (field, child, f)))
+            .collect()
+    }
+}
+
+// another tree with nodes in this one, e.g. `Expr` in `Stmt`, whose traits are in `module`
+struct Foreign {
+    // e.g. `Expr`
+    base: Ident,
+    // e.g. `ExprId`
+    id: Ident,
+    // e.g. `expr`
+    param: Ident,
+    // e.g. `crate::expr`
+    module: Path,
+}
+
+// `call` for every handle in a child field
+fn each_child(child: &Child, field: TokenStream2, call: impl Fn(TokenStream2) -> TokenStream2) -> TokenStream2 {
+    match child {
+        Child::One => call(field),
+        Child::Optional => {
+            let step = call(quote!(v));
+            quote! {
+                if let Some(v) = #field {
+                    #step
+                }
+            }
+        }
+        Child::Many => {
+            let step = call(quote!(*v));
+            quote! {
+                for v in &#field {
+                    #step
+                }
+            }
+        }
+    }
+}
+
+// names shared by all generated items of one tree
+struct Tree<'a> {
+    // e.g. `Expr`
+    base: &'a Ident,
+    // e.g. `ExprId`
+    id: Ident,
+    // e.g. `expr`
+    param: Ident,
+    // the arena type, e.g. `Ast`
+    arena: Path,
+    nodes: Vec<Node<'a>>,
+    foreign: Vec<Foreign>,
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut prev_is_upper = false;
+
+    for c in name.chars() {
+        if c.is_uppercase() {
+            if !prev_is_upper && !result.is_empty() {
+                result.push('_');
+            }
+            prev_is_upper = true;
+            result.push(c.to_ascii_lowercase());
+        } else {
+            prev_is_upper = false;
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// Turns an enum of node variants into an arena-allocated AST:
+///
+/// ```ignore
+/// #[ast(arena = Ast)]
+/// #[derive(Debug, Clone)]
+/// pub enum Expr {
+///     Unary { operator: Token, right: ExprId },
+/// }
+/// ```
+///
+/// becomes `enum Expr { Unary(UnaryExpr) }` with a `UnaryExpr` struct per variant, the
+/// `ExprId` handle, the `Visitor`/`ExprVisitor`, `ExprVisitorMut`, `MutVisitor` and `Fold`
+/// traits and the `walk_*` helpers. Attributes on the enum are copied to every struct.
+///
+/// Fields of type `ExprId`, `Option<ExprId>` and `Vec<ExprId>` are the children walked
+/// by default. The arena must implement `Index<ExprId>`, `IndexMut<ExprId>` and have an
+/// `add_expr(Expr) -> ExprId` method.
+///
+/// `#[ast(arena = Ast, children(Expr = crate::expr))]` on another tree walks and folds its
+/// `ExprId` fields too: its `StmtVisitorMut`, `MutVisitor` and `Fold` extend the ones of
+/// `Expr` in `crate::expr`.
+#[proc_macro_attribute]
+pub fn ast(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut arena = None;
+    let mut foreign = vec![];
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("arena") {
+            arena = Some(meta.value()?.parse()?);
+            Ok(())
+        } else if meta.path.is_ident("children") {
+            meta.parse_nested_meta(|meta| {
+                let Some(base) = meta.path.get_ident().cloned() else {
+                    return Err(meta.error("expected `<tree> = <module>`"));
+                };
+                foreign.push(Foreign {
+                    id: format_ident!("{}Id", base),
+                    param: format_ident!("{}", snake_case(&base.to_string())),
+                    module: meta.value()?.parse()?,
+                    base,
+                });
+                Ok(())
+            })
+        } else {
+            Err(meta.error("expected `arena = <type>` or `children(<tree> = <module>)`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as ItemEnum);
+
+    let Some(arena) = arena else {
+        return syn::Error::new_spanned(&item.ident, "missing `#[ast(arena = <type>)]`")
+            .to_compile_error()
+            .into();
+    };
+    match expand(&item, arena, foreign) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(item: &ItemEnum, arena: Path, foreign: Vec<Foreign>) -> syn::Result<TokenStream2> {
+    let base = &item.ident;
+    let tree = Tree {
+        base,
+        id: format_ident!("{}Id", base),
+        param: format_ident!("{}", snake_case(&base.to_string())),
+        arena,
+        nodes: item
+            .variants
+            .iter()
+            .map(|v| Node::new(v, base))
+            .collect::<syn::Result<Vec<_>>>()?,
+        foreign,
+    };
+
+    let types = gen_types(&item.attrs, &item.vis, &tree);
+    let visitor = gen_visitor(&tree);
+    let visitor_mut = gen_visitor_mut(&tree);
+    let mut_visitor = gen_mut_visitor(&tree);
+    let fold = gen_fold(&tree);
+
+    Ok(quote! {
+        #types
+        #visitor
+        #visitor_mut
+        #mut_visitor
+        #fold
+    })
+}
+
+fn gen_types(attrs: &[Attribute], vis: &Visibility, tree: &Tree) -> TokenStream2 {
+    let Tree { base, id, arena, .. } = tree;
+    let variants = tree.nodes.iter().map(|n| {
+        let (name, struct_name) = (n.name, &n.struct_name);
+        quote!(#name(#struct_name))
+    });
+    let structs = tree.nodes.iter().map(|n| {
+        let struct_name = &n.struct_name;
+        let fields = n.fields.iter().map(|f| {
+            let (attrs, name, ty) = (&f.attrs, &f.ident, &f.ty);
+            quote!(#(#attrs)* pub #name: #ty)
+        });
+        quote! {
+            #(#attrs)*
+            #vis struct #struct_name {
+                #(#fields,)*
+            }
+        }
+    });
+
+    quote! {
+        // handle of a node in the arena
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #vis struct #id(u32);
+        impl #id {
+            pub fn new(index: usize) -> Self {
+                Self(u32::try_from(index).expect("too many nodes"))
+            }
+            pub fn index(self) -> usize {
+                self.0 as usize
+            }
+            pub fn visit<R>(self, ast: &#arena, visitor: &dyn Visitor<R>) -> R {
+                visitor.visit(ast, self)
+            }
+        }
+        #(#attrs)*
+        #vis enum #base {
+            #(#variants,)*
+        }
+        #(#structs)*
+    }
+}
+
+fn gen_visitor(tree: &Tree) -> TokenStream2 {
+    let Tree { base, id, param, arena, .. } = tree;
+    let trait_name = format_ident!("{}Visitor", base);
+    let methods = tree.nodes.iter().map(|n| {
+        let (method, struct_name) = (format_ident!("visit_{}", n.snake_name), &n.struct_name);
+        quote!(fn #method(&self, ast: &#arena, id: #id, #param: &#struct_name) -> R;)
+    });
+    let arms = tree.nodes.iter().map(|n| {
+        let (name, method) = (n.name, format_ident!("visit_{}", n.snake_name));
+        quote!(#base::#name(v) => self.#method(ast, #param, v))
+    });
+
+    quote! {
+        pub trait Visitor<R> {
+            fn visit(&self, ast: &#arena, #param: #id) -> R;
+        }
+        pub trait #trait_name<R> {
+            #(#methods)*
+        }
+        impl<T, R> Visitor<R> for T
+        where
+            T: #trait_name<R>,
+        {
+            fn visit(&self, ast: &#arena, #param: #id) -> R {
+                match &ast[#param] {
+                    #(#arms,)*
+                }
+            }
+        }
+    }
+}
+
+// like the visitor, but with `&mut self` for stateful passes, every method walks into the
+// children by default and gives the default result
+fn gen_visitor_mut(tree: &Tree) -> TokenStream2 {
+    let Tree { base, id, param, arena, .. } = tree;
+    let trait_name = format_ident!("{}VisitorMut", base);
+    let dispatch = format_ident!("visit_{}", param);
+    let methods = tree.nodes.iter().map(|n| {
+        let (method, struct_name) = (format_ident!("visit_{}", n.snake_name), &n.struct_name);
+        let walk = format_ident!("walk_{}", n.snake_name);
+        quote! {
+            fn #method(&mut self, ast: &#arena, id: #id, #param: &#struct_name) -> R {
+                #walk(self, ast, #param);
+                R::default()
+            }
+        }
+    });
+    let arms = tree.nodes.iter().map(|n| {
+        let (name, method) = (n.name, format_ident!("visit_{}", n.snake_name));
+        quote!(#base::#name(v) => self.#method(ast, #param, v))
+    });
+    let supers = tree.foreign.iter().map(|f| {
+        let (module, visitor) = (&f.module, format_ident!("{}VisitorMut", f.base));
+        quote!(#module::#visitor<R>)
+    });
+    let walks = tree.nodes.iter().map(|n| {
+        let struct_name = &n.struct_name;
+        let walk = format_ident!("walk_{}", n.snake_name);
+        let own = n.children(id).into_iter().map(|(field, child)| {
+            each_child(&child, quote!(#param.#field), |v| quote!(visitor.#dispatch(ast, #v);))
+        });
+        let foreign = n.foreign_children(&tree.foreign).into_iter().map(|(field, child, f)| {
+            let (module, visitor) = (&f.module, format_ident!("{}VisitorMut", f.base));
+            let dispatch = format_ident!("visit_{}", f.param);
+            each_child(&child, quote!(#param.#field), |v| {
+                quote!(<V as #module::#visitor<R>>::#dispatch(visitor, ast, #v);)
+            })
+        });
+        let steps = own.chain(foreign);
+        quote! {
+            #[allow(unused_variables)]
+            pub fn #walk<R: Default, V: #trait_name<R> + ?Sized>(visitor: &mut V, ast: &#arena, #param: &#struct_name) {
+                #(#steps)*
+            }
+        }
+    });
+
+    quote! {
+        pub trait #trait_name<R: Default>: #(#supers)+* {
+            fn #dispatch(&mut self, ast: &#arena, #param: #id) -> R {
+                match &ast[#param] {
+                    #(#arms,)*
+                }
+            }
+            #(#methods)*
+        }
+        #(#walks)*
+    }
+}
+
+// rewrites nodes in place, every method walks into the children by default
+fn gen_mut_visitor(tree: &Tree) -> TokenStream2 {
+    let Tree { base, id, param, arena, .. } = tree;
+    let visit_base = format_ident!("visit_{}_mut", param);
+    let walk_base = format_ident!("walk_{}_mut", param);
+    let methods = tree.nodes.iter().map(|n| {
+        let method = format_ident!("visit_{}_mut", n.snake_name);
+        let walk = format_ident!("walk_{}_mut", n.snake_name);
+        quote! {
+            fn #method(&mut self, ast: &mut #arena, #param: #id) {
+                #walk(self, ast, #param)
+            }
+        }
+    });
+    let arms = tree.nodes.iter().map(|n| {
+        let (name, method) = (n.name, format_ident!("visit_{}_mut", n.snake_name));
+        quote!(#base::#name(_) => visitor.#method(ast, #param))
+    });
+    let walks = tree.nodes.iter().map(|n| {
+        let name = n.name;
+        let walk = format_ident!("walk_{}_mut", n.snake_name);
+        let own = n.children(id).into_iter().map(|(field, child)| {
+            let step = each_child(&child, quote!(#field), |v| quote!(visitor.#visit_base(ast, #v);));
+            (field, child, step)
+        });
+        let foreign = n.foreign_children(&tree.foreign).into_iter().map(|(field, child, f)| {
+            let (module, visit) = (&f.module, format_ident!("visit_{}_mut", f.param));
+            let step = each_child(&child, quote!(#field), |v| {
+                quote!(<V as #module::MutVisitor>::#visit(visitor, ast, #v);)
+            });
+            (field, child, step)
+        });
+        let children: Vec<_> = own.chain(foreign).collect();
+        // copy the child handles out so the arena can be borrowed mutably while walking
+        let fields = children.iter().map(|(field, ..)| field);
+        let copies = children.iter().map(|(field, child, _)| match child {
+            Child::Many => quote!(node.#field.clone()),
+            _ => quote!(node.#field),
+        });
+        let steps = children.iter().map(|(.., step)| step);
+        quote! {
+            #[allow(unused_variables, irrefutable_let_patterns)]
+            pub fn #walk<V: MutVisitor + ?Sized>(visitor: &mut V, ast: &mut #arena, #param: #id) {
+                let #base::#name(node) = &ast[#param] else {
+                    unreachable!("{:?} is not a {}", #param, stringify!(#name));
+                };
+                let (#(#fields,)*) = (#(#copies,)*);
+                #(#steps)*
+            }
+        }
+    });
+
+    let supers = tree.foreign.iter().map(|f| {
+        let module = &f.module;
+        quote!(#module::MutVisitor)
+    });
+
+    quote! {
+        pub trait MutVisitor: #(#supers)+* {
+            fn #visit_base(&mut self, ast: &mut #arena, #param: #id) {
+                #walk_base(self, ast, #param)
+            }
+            #(#methods)*
+        }
+        pub fn #walk_base<V: MutVisitor + ?Sized>(visitor: &mut V, ast: &mut #arena, #param: #id) {
+            match &ast[#param] {
+                #(#arms,)*
+            }
+        }
+        #(#walks)*
+    }
+}
+
+// rebuilds the tree from one arena into another, every method folds the children by default
+fn gen_fold(tree: &Tree) -> TokenStream2 {
+    let Tree { base, id, param, arena, .. } = tree;
+    let fold_base = format_ident!("fold_{}", param);
+    let walk_base = format_ident!("walk_fold_{}", param);
+    let add = format_ident!("add_{}", param);
+    let methods = tree.nodes.iter().map(|n| {
+        let struct_name = &n.struct_name;
+        let method = format_ident!("fold_{}", n.snake_name);
+        let walk = format_ident!("walk_fold_{}", n.snake_name);
+        quote! {
+            fn #method(&mut self, ast: &#arena, out: &mut #arena, #param: &#struct_name) -> #id {
+                #walk(self, ast, out, #param)
+            }
+        }
+    });
+    let arms = tree.nodes.iter().map(|n| {
+        let (name, method) = (n.name, format_ident!("fold_{}", n.snake_name));
+        quote!(#base::#name(v) => folder.#method(ast, out, v))
+    });
+    let walks = tree.nodes.iter().map(|n| {
+        let (name, struct_name) = (n.name, &n.struct_name);
+        let walk = format_ident!("walk_fold_{}", n.snake_name);
+        let fields = n.fields.iter().map(|f| {
+            let field = &f.ident;
+            // the children of another tree are folded by its `Fold`
+            let (child, fold) = match Child::of(&f.ty, id) {
+                Some(child) => (child, quote!(<F as Fold>::#fold_base)),
+                None => match tree.foreign.iter().find_map(|t| Child::of(&f.ty, &t.id).map(|c| (c, t))) {
+                    Some((child, t)) => {
+                        let (module, fold) = (&t.module, format_ident!("fold_{}", t.param));
+                        (child, quote!(<F as #module::Fold>::#fold))
+                    }
+                    None => return quote!(#field: #param.#field.clone()),
+                },
+            };
+            match child {
+                Child::One => quote!(#field: #fold(folder, ast, out, #param.#field)),
+                Child::Optional => quote!(#field: #param.#field.map(|v| #fold(folder, ast, out, v))),
+                Child::Many => quote! {
+                    #field: #param.#field.iter().map(|v| #fold(folder, ast, out, *v)).collect()
+                },
+            }
+        });
+        quote! {
+            #[allow(unused_variables)]
+            pub fn #walk<F: Fold + ?Sized>(folder: &mut F, ast: &#arena, out: &mut #arena, #param: &#struct_name) -> #id {
+                let node = #struct_name {
+                    #(#fields,)*
+                };
+                out.#add(#base::#name(node))
+            }
+        }
+    });
+
+    let supers = tree.foreign.iter().map(|f| {
+        let module = &f.module;
+        quote!(#module::Fold)
+    });
+
+    quote! {
+        pub trait Fold: #(#supers)+* {
+            fn #fold_base(&mut self, ast: &#arena, out: &mut #arena, #param: #id) -> #id {
+                #walk_base(self, ast, out, #param)
+            }
+            #(#methods)*
+        }
+        pub fn #walk_base<F: Fold + ?Sized>(folder: &mut F, ast: &#arena, out: &mut #arena, #param: #id) -> #id {
+            match &ast[#param] {
+                #(#arms,)*
+            }
+        }
+        #(#walks)*
+    }
+}
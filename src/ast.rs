@@ -0,0 +1,65 @@
+use std::ops::{Index, IndexMut};
+
+use crate::{
+    expr::{Expr, ExprId},
+    stmt::{Stmt, StmtId},
+};
+
+// arena owning every node of a program, nodes refer to each other by id
+#[derive(Debug, Default, Clone)]
+pub struct Ast {
+    exprs: Vec<Expr>,
+    stmts: Vec<Stmt>,
+}
+
+impl Ast {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_expr(&mut self, expr: Expr) -> ExprId {
+        self.exprs.push(expr);
+        ExprId::new(self.exprs.len() - 1)
+    }
+
+    pub fn add_stmt(&mut self, stmt: Stmt) -> StmtId {
+        self.stmts.push(stmt);
+        StmtId::new(self.stmts.len() - 1)
+    }
+
+    pub fn expr_count(&self) -> usize {
+        self.exprs.len()
+    }
+
+    pub fn stmt_count(&self) -> usize {
+        self.stmts.len()
+    }
+}
+
+impl Index<ExprId> for Ast {
+    type Output = Expr;
+
+    fn index(&self, id: ExprId) -> &Self::Output {
+        &self.exprs[id.index()]
+    }
+}
+
+impl IndexMut<ExprId> for Ast {
+    fn index_mut(&mut self, id: ExprId) -> &mut Self::Output {
+        &mut self.exprs[id.index()]
+    }
+}
+
+impl Index<StmtId> for Ast {
+    type Output = Stmt;
+
+    fn index(&self, id: StmtId) -> &Self::Output {
+        &self.stmts[id.index()]
+    }
+}
+
+impl IndexMut<StmtId> for Ast {
+    fn index_mut(&mut self, id: StmtId) -> &mut Self::Output {
+        &mut self.stmts[id.index()]
+    }
+}
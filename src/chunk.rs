@@ -0,0 +1,232 @@
+use crate::interpreter::Value;
+
+// an instruction, its operands follow it in `Chunk::code`
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    // u16 index into the constants
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    // u16 index of the name in the constants
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    Negate,
+    Not,
+    BitNot,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    FloorDivide,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Print,
+    // u16 offset forward from the end of the instruction
+    Jump,
+    // pops the condition, u16 offset as for `Jump`
+    JumpIfFalse,
+    Return,
+}
+
+// in the order of the discriminants, to decode bytes
+const OPCODES: [OpCode; 33] = [
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::DefineGlobal,
+    OpCode::GetGlobal,
+    OpCode::SetGlobal,
+    OpCode::Negate,
+    OpCode::Not,
+    OpCode::BitNot,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Modulo,
+    OpCode::FloorDivide,
+    OpCode::Power,
+    OpCode::BitAnd,
+    OpCode::BitOr,
+    OpCode::BitXor,
+    OpCode::ShiftLeft,
+    OpCode::ShiftRight,
+    OpCode::Equal,
+    OpCode::NotEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Return,
+];
+
+impl OpCode {
+    // as in clox, `GetGlobal` is `OP_GET_GLOBAL`
+    pub fn name(self) -> String {
+        let mut name = "OP".to_owned();
+        for c in format!("{self:?}").chars() {
+            if c.is_ascii_uppercase() {
+                name.push('_');
+            }
+            name.push(c.to_ascii_uppercase());
+        }
+        name
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+// a compiled program
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    // source line of every byte in `code`
+    pub lines: Vec<u32>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: u32) {
+        self.code.push(byte);
+        self.lines.push(line);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: u32) {
+        self.write(op as u8, line);
+    }
+
+    pub fn write_u16(&mut self, v: u16, line: u32) {
+        for byte in v.to_be_bytes() {
+            self.write(byte, line);
+        }
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // index of the value in the constants, equal values share a slot,
+    // `None` once there are too many for a u16 operand
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        let index = match self.constants.iter().position(|v| same(v, &value)) {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        u16::try_from(index).ok()
+    }
+
+    // every instruction with its offset, line and operands, under a `== name ==` header
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {name} ==\n");
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let (text, next) = self.disassemble_instruction(offset);
+            out.push_str(&text);
+            out.push('\n');
+            offset = next;
+        }
+        out
+    }
+
+    // the instruction at `offset` and the offset of the next one,
+    // a line equal to the one of the previous instruction is shown as `|`
+    pub fn disassemble_instruction(&self, offset: usize) -> (String, usize) {
+        let line = if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
+            "   |".to_owned()
+        } else {
+            format!("{:4}", self.lines[offset])
+        };
+        let prefix = format!("{offset:04} {line} ");
+
+        let Ok(op) = OpCode::try_from(self.code[offset]) else {
+            return (format!("{prefix}unknown opcode {}", self.code[offset]), offset + 1);
+        };
+        let text = match op {
+            OpCode::Constant | OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                let index = self.read_u16(offset + 1);
+                format!("{:<16} {index:4} '{}'", op.name(), self.constants[index as usize])
+            }
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                let jump = self.read_u16(offset + 1);
+                format!("{:<16} {offset:4} -> {}", op.name(), offset + 3 + jump as usize)
+            }
+            _ => return (format!("{prefix}{}", op.name()), offset + 1),
+        };
+        (format!("{prefix}{text}"), offset + 3)
+    }
+}
+
+// numbers by bits, so 0 and -0 keep their own slots
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b,
+    }
+}
+
+#[test]
+fn opcode_test() {
+    for (i, op) in OPCODES.iter().enumerate() {
+        assert_eq!(i, *op as usize);
+        assert_eq!(Ok(*op), OpCode::try_from(i as u8));
+    }
+    assert_eq!(Err(33), OpCode::try_from(33));
+}
+
+#[test]
+fn disassemble_test() {
+    use crate::{compiler, parser::Parser, scanner::Scanner};
+
+    let mut parser = Parser::new(Scanner::new("var a = 1;\nprint a ? -a : \"b\";".to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    let chunk = compiler::compile(&parser.ast, &stmts).unwrap();
+    assert_eq!(
+        "\
+== test ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_GET_GLOBAL       1 'a'
+0009    | OP_JUMP_IF_FALSE    9 -> 19
+0012    | OP_GET_GLOBAL       1 'a'
+0015    | OP_NEGATE
+0016    | OP_JUMP            16 -> 22
+0019    | OP_CONSTANT         2 'b'
+0022    | OP_PRINT
+0023    | OP_RETURN
+",
+        chunk.disassemble("test")
+    );
+}
@@ -0,0 +1,289 @@
+// the command line of `lox`, parsed by hand since it is small
+
+use std::path::Path;
+
+use crate::vm::VmOptions;
+
+pub const USAGE: &str = "\
+Usage: lox [command] [options]
+
+Commands:
+    repl                        start an interactive prompt, the default
+    run [--vm] <file>...        run scripts in order, a directory runs the .lox files
+                                in it, `lox <file>...` does the same, --vm runs them
+                                on the bytecode virtual machine
+    disasm <file>               print the compiled bytecode
+    compile <file> [-o <out>]   write the bytecode to <out>, by default the file with
+                                a .loxc extension, which `lox run` accepts
+    check <file>                report scan and parse errors without running
+    fmt [--check] <file>...     reformat files in place
+    ast [--format=sexpr|json] <file>
+                                print the syntax tree
+    tokens [--json] <file>      print the tokens
+
+VM options, which imply --vm:
+    --trace                     print the stack and each instruction
+    --stress-gc                 collect garbage before every allocation
+    --gc-growth=<factor>        collect once the heap is this many times larger than
+                                after the last collection, 2 by default
+
+Options:
+    -e <code> [--vm]            run code given on the command line
+    -h, --help                  print this help
+    -V, --version               print the version
+
+A <file> of `-` reads standard input.";
+
+// what runs the scripts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Tree,
+    Vm(VmOptions),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Repl,
+    Run { paths: Vec<String>, backend: Backend },
+    Disasm(String),
+    Compile { path: String, output: String },
+    Eval { code: String, backend: Backend },
+    Check(String),
+    Fmt { paths: Vec<String>, check: bool },
+    Ast { path: String, format: String },
+    Tokens { path: String, json: bool },
+    Help,
+    Version,
+}
+
+// `args` without the program name, `Err` is a usage error to report
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let Some((first, rest)) = args.split_first() else {
+        return Ok(Command::Repl);
+    };
+
+    let command = match first.as_str() {
+        "-h" | "--help" => Command::Help,
+        "-V" | "--version" => Command::Version,
+        "-e" => return eval_of(args),
+        "repl" => Command::Repl,
+        "run" => return run_of(first, rest),
+        "check" => return Ok(Command::Check(single_path(first, rest)?)),
+        "disasm" => return Ok(Command::Disasm(single_path(first, rest)?)),
+        "compile" => {
+            let mut output = None;
+            let mut paths = vec![];
+            let mut args = rest.iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-o" => match args.next() {
+                        Some(out) => output = Some(out.to_owned()),
+                        None => return Err("-o needs the output file".to_owned()),
+                    },
+                    _ => paths.push(arg.to_owned()),
+                }
+            }
+            let path = single_path(first, &paths)?;
+            let output = match output {
+                Some(output) => output,
+                None if path == "-" => return Err("compile needs -o to read standard input".to_owned()),
+                None => Path::new(&path).with_extension("loxc").to_string_lossy().into_owned(),
+            };
+            return Ok(Command::Compile { path, output });
+        }
+        "fmt" => {
+            let check = rest.iter().any(|arg| arg == "--check");
+            let paths: Vec<String> = rest.iter().filter(|arg| *arg != "--check").cloned().collect();
+            return Ok(Command::Fmt {
+                paths: paths_of(first, &paths)?,
+                check,
+            });
+        }
+        "ast" => {
+            let mut format = "sexpr".to_owned();
+            let mut paths = vec![];
+            for arg in rest {
+                match arg.strip_prefix("--format=") {
+                    Some(f @ ("json" | "sexpr")) => format = f.to_owned(),
+                    Some(f) => return Err(format!("unknown format '{f}', expect json or sexpr")),
+                    None => paths.push(arg.to_owned()),
+                }
+            }
+            return Ok(Command::Ast {
+                path: single_path(first, &paths)?,
+                format,
+            });
+        }
+        "tokens" => {
+            let json = rest.iter().any(|arg| arg == "--json");
+            let paths: Vec<String> = rest.iter().filter(|arg| *arg != "--json").cloned().collect();
+            return Ok(Command::Tokens {
+                path: single_path(first, &paths)?,
+                json,
+            });
+        }
+        option if rest.iter().any(|arg| arg == "-e") && vm_option(option, &mut VmOptions::default())? => {
+            return eval_of(args)
+        }
+        path if !is_option(path) || vm_option(path, &mut VmOptions::default()) != Ok(false) => {
+            return run_of("run", args)
+        }
+        option => return Err(format!("unknown option '{option}'")),
+    };
+
+    match rest.first() {
+        Some(arg) => Err(format!("unexpected argument '{arg}'")),
+        None => Ok(command),
+    }
+}
+
+// `-` alone means standard input, not an option
+fn is_option(arg: &str) -> bool {
+    arg.starts_with('-') && arg != "-"
+}
+
+fn run_of(command: &str, args: &[String]) -> Result<Command, String> {
+    let mut options = VmOptions::default();
+    let mut vm = false;
+    let mut paths = vec![];
+    for arg in args {
+        if vm_option(arg, &mut options)? {
+            vm = true;
+        } else {
+            paths.push(arg.to_owned());
+        }
+    }
+    Ok(Command::Run {
+        paths: paths_of(command, &paths)?,
+        backend: if vm { Backend::Vm(options) } else { Backend::Tree },
+    })
+}
+
+// `-e <code>` and the options of the VM, in any order
+fn eval_of(args: &[String]) -> Result<Command, String> {
+    let mut options = VmOptions::default();
+    let mut vm = false;
+    let mut code = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-e" && code.is_none() {
+            match args.next() {
+                Some(c) => code = Some(c.to_owned()),
+                None => return Err("-e needs the code to run".to_owned()),
+            }
+        } else if vm_option(arg, &mut options)? {
+            vm = true;
+        } else {
+            return Err("-e takes a single argument, quote the code".to_owned());
+        }
+    }
+    Ok(Command::Eval {
+        code: code.expect("-e is in the arguments"),
+        backend: if vm { Backend::Vm(options) } else { Backend::Tree },
+    })
+}
+
+// whether `arg` is an option of the VM, set in `options`
+fn vm_option(arg: &str, options: &mut VmOptions) -> Result<bool, String> {
+    match arg {
+        "--vm" => {}
+        "--trace" => options.trace = true,
+        "--stress-gc" => options.stress_gc = true,
+        _ => match arg.strip_prefix("--gc-growth=") {
+            Some(factor) => match factor.parse::<f64>() {
+                Ok(factor) if factor >= 1.0 => options.gc_growth = factor,
+                _ => return Err(format!("invalid --gc-growth '{factor}', expect a number of at least 1")),
+            },
+            None => return Ok(false),
+        },
+    }
+    Ok(true)
+}
+
+fn paths_of(command: &str, args: &[String]) -> Result<Vec<String>, String> {
+    if let Some(option) = args.iter().find(|arg| is_option(arg)) {
+        return Err(format!("unknown option '{option}' for {command}"));
+    }
+    if args.is_empty() {
+        return Err(format!("{command} needs at least one file"));
+    }
+    Ok(args.to_vec())
+}
+
+fn single_path(command: &str, args: &[String]) -> Result<String, String> {
+    match args {
+        [path] if !is_option(path) => Ok(path.to_owned()),
+        [option] => Err(format!("unknown option '{option}' for {command}")),
+        [] => Err(format!("{command} needs a file")),
+        _ => Err(format!("{command} takes a single file")),
+    }
+}
+
+#[test]
+fn parse_test() {
+    let parse = |args: &[&str]| parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>());
+
+    assert_eq!(Ok(Command::Repl), parse(&[]));
+    let run = |paths: &[&str], backend| Command::Run {
+        paths: paths.iter().map(|path| path.to_string()).collect(),
+        backend,
+    };
+    let vm = |trace, stress_gc, gc_growth| Backend::Vm(VmOptions {
+        trace,
+        stress_gc,
+        gc_growth,
+    });
+    assert_eq!(Ok(run(&["a.lox"], Backend::Tree)), parse(&["a.lox"]));
+    assert_eq!(Ok(run(&["-"], Backend::Tree)), parse(&["run", "-"]));
+    assert_eq!(Ok(run(&["a.lox", "dir"], Backend::Tree)), parse(&["a.lox", "dir"]));
+    assert_eq!(Ok(run(&["a.lox"], vm(false, false, 2.0))), parse(&["run", "--vm", "a.lox"]));
+    assert_eq!(Ok(run(&["a.lox"], vm(false, false, 2.0))), parse(&["--vm", "a.lox"]));
+    assert_eq!(Ok(run(&["a.lox"], vm(true, false, 2.0))), parse(&["a.lox", "--trace"]));
+    assert_eq!(
+        Ok(run(&["a.lox"], vm(false, true, 1.5))),
+        parse(&["--stress-gc", "a.lox", "--gc-growth=1.5"])
+    );
+    assert_eq!(
+        Err("invalid --gc-growth '0.5', expect a number of at least 1".to_owned()),
+        parse(&["run", "--gc-growth=0.5", "a.lox"])
+    );
+    assert_eq!(Ok(Command::Disasm("a.lox".to_owned())), parse(&["disasm", "a.lox"]));
+    let compile = |path: &str, output: &str| Command::Compile {
+        path: path.to_owned(),
+        output: output.to_owned(),
+    };
+    assert_eq!(Ok(compile("dir/a.lox", "dir/a.loxc")), parse(&["compile", "dir/a.lox"]));
+    assert_eq!(Ok(compile("a.lox", "b")), parse(&["compile", "a.lox", "-o", "b"]));
+    assert_eq!(Ok(compile("-", "b")), parse(&["compile", "-o", "b", "-"]));
+    assert_eq!(Err("-o needs the output file".to_owned()), parse(&["compile", "a.lox", "-o"]));
+    let eval = |code: &str, backend| Command::Eval {
+        code: code.to_owned(),
+        backend,
+    };
+    assert_eq!(Ok(eval("1 + 2", Backend::Tree)), parse(&["-e", "1 + 2"]));
+    assert_eq!(Ok(eval("1", vm(false, false, 2.0))), parse(&["-e", "1", "--vm"]));
+    assert_eq!(Ok(eval("1", vm(true, false, 2.0))), parse(&["--trace", "-e", "1"]));
+    assert_eq!(Err("-e needs the code to run".to_owned()), parse(&["--vm", "-e"]));
+    assert_eq!(Err("-e takes a single argument, quote the code".to_owned()), parse(&["-e", "1", "2"]));
+    assert_eq!(Ok(Command::Check("a.lox".to_owned())), parse(&["check", "a.lox"]));
+    assert_eq!(Ok(Command::Version), parse(&["--version"]));
+    assert_eq!(
+        Ok(Command::Fmt {
+            paths: vec!["a.lox".to_owned(), "b.lox".to_owned()],
+            check: true
+        }),
+        parse(&["fmt", "a.lox", "--check", "b.lox"])
+    );
+    assert_eq!(
+        Ok(Command::Ast {
+            path: "a.lox".to_owned(),
+            format: "json".to_owned()
+        }),
+        parse(&["ast", "--format=json", "a.lox"])
+    );
+
+    assert_eq!(Err("unknown option '--foo'".to_owned()), parse(&["--foo"]));
+    assert_eq!(Err("run needs at least one file".to_owned()), parse(&["run"]));
+    assert_eq!(Err("unknown option '-x' for run".to_owned()), parse(&["a.lox", "-x"]));
+    assert_eq!(Err("unknown option '--json' for check".to_owned()), parse(&["check", "--json"]));
+}
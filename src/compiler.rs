@@ -0,0 +1,186 @@
+use crate::{
+    ast::Ast,
+    chunk::{Chunk, OpCode},
+    expr::{
+        AssignExpr, BinaryExpr, ConditionalExpr, ExprId, ExprVisitorMut, GroupingExpr, LiteralExpr,
+        PostfixExpr, UnaryExpr, VariableExpr,
+    },
+    interpreter::Value,
+    runner,
+    stmt::{ExpressionStmt, PrintStmt, StmtId, StmtVisitorMut, VarStmt},
+    token::{LiteralValue, TokenType},
+};
+
+// compiles a program to bytecode in one pass over the tree, `None` if it does not fit
+// the u16 operands, which is reported already
+pub fn compile(ast: &Ast, stmts: &[StmtId]) -> Option<Chunk> {
+    let mut compiler = Compiler {
+        chunk: Chunk::new(),
+        line: 1,
+        had_error: false,
+    };
+    for stmt in stmts {
+        compiler.visit_stmt(ast, *stmt);
+    }
+    compiler.emit(OpCode::Return);
+
+    (!compiler.had_error).then_some(compiler.chunk)
+}
+
+struct Compiler {
+    chunk: Chunk,
+    // only operators and names keep their token, other nodes use the last line seen
+    line: u32,
+    had_error: bool,
+}
+
+impl Compiler {
+    fn emit(&mut self, op: OpCode) {
+        self.chunk.write_op(op, self.line);
+    }
+
+    // an instruction with a constant operand
+    fn emit_constant(&mut self, op: OpCode, value: Value) {
+        match self.chunk.add_constant(value) {
+            Some(index) => {
+                self.emit(op);
+                self.chunk.write_u16(index, self.line);
+            }
+            None => self.error("Too many constants in one chunk."),
+        }
+    }
+
+    // a jump to patch once the target is known, returns where its operand is
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit(op);
+        self.chunk.write_u16(u16::MAX, self.line);
+        self.chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, operand: usize) {
+        match u16::try_from(self.chunk.code.len() - operand - 2) {
+            Ok(offset) => self.chunk.code[operand..operand + 2].copy_from_slice(&offset.to_be_bytes()),
+            Err(_) => self.error("Too much code to jump over."),
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        if !self.had_error {
+            runner::error(self.line, message);
+        }
+        self.had_error = true;
+    }
+}
+
+impl ExprVisitorMut<()> for Compiler {
+    fn visit_literal(&mut self, _: &Ast, _: ExprId, expr: &LiteralExpr) {
+        match &expr.value {
+            LiteralValue::Nil => self.emit(OpCode::Nil),
+            LiteralValue::Bool(true) => self.emit(OpCode::True),
+            LiteralValue::Bool(false) => self.emit(OpCode::False),
+            value => self.emit_constant(OpCode::Constant, Value::from(value)),
+        }
+    }
+
+    fn visit_unary(&mut self, ast: &Ast, _: ExprId, expr: &UnaryExpr) {
+        self.visit_expr(ast, expr.right);
+        self.line = expr.operator.line;
+        self.emit(match expr.operator.token_type {
+            TokenType::Minus => OpCode::Negate,
+            TokenType::Bang => OpCode::Not,
+            TokenType::Tilde => OpCode::BitNot,
+            _ => unreachable!("unknown unary operator {:?}", expr.operator),
+        });
+    }
+
+    fn visit_binary(&mut self, ast: &Ast, _: ExprId, expr: &BinaryExpr) {
+        self.visit_expr(ast, expr.left);
+        if expr.operator.token_type == TokenType::Comma {
+            self.emit(OpCode::Pop);
+            self.visit_expr(ast, expr.right);
+            return;
+        }
+
+        self.visit_expr(ast, expr.right);
+        self.line = expr.operator.line;
+        self.emit(match expr.operator.token_type {
+            TokenType::Plus => OpCode::Add,
+            TokenType::Minus => OpCode::Subtract,
+            TokenType::Star => OpCode::Multiply,
+            TokenType::Slash => OpCode::Divide,
+            TokenType::Percent => OpCode::Modulo,
+            TokenType::SlashSlash => OpCode::FloorDivide,
+            TokenType::StarStar => OpCode::Power,
+            TokenType::Ampersand => OpCode::BitAnd,
+            TokenType::Pipe => OpCode::BitOr,
+            TokenType::Caret => OpCode::BitXor,
+            TokenType::LessLess => OpCode::ShiftLeft,
+            TokenType::GreaterGreater => OpCode::ShiftRight,
+            TokenType::EqualEqual => OpCode::Equal,
+            TokenType::BangEqual => OpCode::NotEqual,
+            TokenType::Less => OpCode::Less,
+            TokenType::LessEqual => OpCode::LessEqual,
+            TokenType::Greater => OpCode::Greater,
+            TokenType::GreaterEqual => OpCode::GreaterEqual,
+            _ => unreachable!("unknown binary operator {:?}", expr.operator),
+        });
+    }
+
+    fn visit_grouping(&mut self, ast: &Ast, _: ExprId, expr: &GroupingExpr) {
+        self.visit_expr(ast, expr.expression);
+    }
+
+    fn visit_conditional(&mut self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) {
+        self.visit_expr(ast, expr.condition);
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.visit_expr(ast, expr.then_branch);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(else_jump);
+        self.visit_expr(ast, expr.else_branch);
+        self.patch_jump(end_jump);
+    }
+
+    fn visit_variable(&mut self, _: &Ast, _: ExprId, expr: &VariableExpr) {
+        self.line = expr.name.line;
+        self.emit_constant(OpCode::GetGlobal, Value::String(expr.name.lexeme.to_string()));
+    }
+
+    fn visit_assign(&mut self, ast: &Ast, _: ExprId, expr: &AssignExpr) {
+        self.visit_expr(ast, expr.value);
+        self.line = expr.name.line;
+        self.emit_constant(OpCode::SetGlobal, Value::String(expr.name.lexeme.to_string()));
+    }
+
+    // the old value stays below the new one, which is set and dropped
+    fn visit_postfix(&mut self, _: &Ast, _: ExprId, expr: &PostfixExpr) {
+        self.line = expr.name.line;
+        let name = Value::String(expr.name.lexeme.to_string());
+        self.emit_constant(OpCode::GetGlobal, name.clone());
+        self.emit_constant(OpCode::GetGlobal, name.clone());
+        self.emit_constant(OpCode::Constant, Value::Number(1.0));
+        self.emit(if expr.operator.token_type == TokenType::PlusPlus { OpCode::Add } else { OpCode::Subtract });
+        self.emit_constant(OpCode::SetGlobal, name);
+        self.emit(OpCode::Pop);
+    }
+}
+
+impl StmtVisitorMut<()> for Compiler {
+    fn visit_expression(&mut self, ast: &Ast, _: StmtId, stmt: &ExpressionStmt) {
+        self.visit_expr(ast, stmt.expression);
+        self.emit(OpCode::Pop);
+    }
+
+    fn visit_print(&mut self, ast: &Ast, _: StmtId, stmt: &PrintStmt) {
+        self.visit_expr(ast, stmt.expression);
+        self.emit(OpCode::Print);
+    }
+
+    fn visit_var(&mut self, ast: &Ast, _: StmtId, stmt: &VarStmt) {
+        match stmt.initializer {
+            Some(initializer) => self.visit_expr(ast, initializer),
+            None => self.emit(OpCode::Nil),
+        }
+        self.line = stmt.name.line;
+        self.emit_constant(OpCode::DefineGlobal, Value::String(stmt.name.lexeme.to_string()));
+    }
+}
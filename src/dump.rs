@@ -0,0 +1,628 @@
+// S-expression and JSON dumps of the AST, and a reader turning the S-expression form back
+// into nodes, so tests can spell expected trees compactly.
+use std::{iter::Peekable, str::Chars};
+
+use crate::{
+    ast::Ast,
+    expr::{
+        AssignExpr, BinaryExpr, ConditionalExpr, Expr, ExprId, ExprVisitor, GroupingExpr,
+        LiteralExpr, PostfixExpr, UnaryExpr, VariableExpr,
+    },
+    parser::Parser,
+    stmt::{ExpressionStmt, PrintStmt, StmtId, StmtVisitor, VarStmt},
+    symbol::Symbol,
+    token::{LiteralValue, Span, Token, TokenType},
+};
+
+// one token per line: position, type, lexeme and literal
+pub fn tokens_to_text(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let position = format!("{}:{}", token.line, token.column);
+        let token_type = format!("{:?}", token.token_type);
+        let literal = match &token.literal {
+            Some(LiteralValue::String(v)) => format!("\"{v}\""),
+            Some(LiteralValue::Nubmer(v)) => v.to_string(),
+            Some(LiteralValue::Bool(v)) => v.to_string(),
+            Some(LiteralValue::Nil) => "nil".to_owned(),
+            None => String::new(),
+        };
+        let line = format!("{position:<8}{token_type:<16}{:<16}{literal}", token.lexeme);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    out
+}
+
+pub fn tokens_to_json(tokens: &[Token]) -> String {
+    let tokens = tokens
+        .iter()
+        .map(|token| {
+            let literal = match &token.literal {
+                Some(LiteralValue::String(v)) => Json::String(v.to_string()),
+                Some(LiteralValue::Nubmer(v)) => Json::Number(*v),
+                Some(LiteralValue::Bool(v)) => Json::Bool(*v),
+                Some(LiteralValue::Nil) | None => Json::Null,
+            };
+            Json::Object(vec![
+                ("type", Json::String(format!("{:?}", token.token_type))),
+                ("lexeme", Json::String(token.lexeme.to_string())),
+                ("literal", literal),
+                ("line", Json::Number(token.line as f64)),
+                ("column", Json::Number(token.column as f64)),
+            ])
+        })
+        .collect();
+
+    let mut out = String::new();
+    Json::Array(tokens).write(&mut out, 0);
+    out
+}
+
+pub fn to_sexpr(ast: &Ast, expr: ExprId) -> String {
+    expr.visit(ast, &SexprWriter)
+}
+
+pub fn to_json(parser: &Parser, expr: ExprId) -> String {
+    let mut out = String::new();
+    expr.visit(&parser.ast, &JsonWriter { parser }).write(&mut out, 0);
+    out
+}
+
+// one statement per line
+pub fn program_to_sexpr(ast: &Ast, stmts: &[StmtId]) -> String {
+    stmts.iter().map(|stmt| stmt.visit(ast, &SexprWriter) + "\n").collect()
+}
+
+pub fn program_to_json(parser: &Parser, stmts: &[StmtId]) -> String {
+    let stmts = stmts.iter().map(|stmt| stmt.visit(&parser.ast, &JsonWriter { parser })).collect();
+    let mut out = String::new();
+    Json::Array(stmts).write(&mut out, 0);
+    out
+}
+
+// like `exercise::ch5::AstVisitor`, but strings are quoted so they can be read back
+struct SexprWriter;
+
+impl SexprWriter {
+    fn list(&self, ast: &Ast, head: &str, exprs: &[ExprId]) -> String {
+        let mut res = format!("({head}");
+        for expr in exprs {
+            res.push(' ');
+            res.push_str(&expr.visit(ast, self));
+        }
+        res.push(')');
+
+        res
+    }
+}
+
+impl ExprVisitor<String> for SexprWriter {
+    fn visit_literal(&self, _: &Ast, _: ExprId, expr: &LiteralExpr) -> String {
+        match &expr.value {
+            LiteralValue::String(v) => format!("\"{v}\""),
+            LiteralValue::Nubmer(v) => v.to_string(),
+            LiteralValue::Bool(v) => v.to_string(),
+            LiteralValue::Nil => "nil".to_owned(),
+        }
+    }
+
+    fn visit_unary(&self, ast: &Ast, _: ExprId, expr: &UnaryExpr) -> String {
+        self.list(ast, &expr.operator.lexeme, &[expr.right])
+    }
+
+    fn visit_binary(&self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> String {
+        self.list(ast, &expr.operator.lexeme, &[expr.left, expr.right])
+    }
+
+    fn visit_grouping(&self, ast: &Ast, _: ExprId, expr: &GroupingExpr) -> String {
+        self.list(ast, "Group", &[expr.expression])
+    }
+
+    fn visit_conditional(&self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> String {
+        self.list(ast, "?:", &[expr.condition, expr.then_branch, expr.else_branch])
+    }
+
+    fn visit_variable(&self, _: &Ast, _: ExprId, expr: &VariableExpr) -> String {
+        expr.name.lexeme.to_string()
+    }
+
+    fn visit_assign(&self, ast: &Ast, _: ExprId, expr: &AssignExpr) -> String {
+        format!("(= {} {})", expr.name.lexeme, expr.value.visit(ast, self))
+    }
+
+    fn visit_postfix(&self, _: &Ast, _: ExprId, expr: &PostfixExpr) -> String {
+        format!("(post{} {})", expr.operator.lexeme, expr.name.lexeme)
+    }
+}
+
+impl StmtVisitor<String> for SexprWriter {
+    fn visit_expression(&self, ast: &Ast, _: StmtId, stmt: &ExpressionStmt) -> String {
+        self.list(ast, "expression", &[stmt.expression])
+    }
+
+    fn visit_print(&self, ast: &Ast, _: StmtId, stmt: &PrintStmt) -> String {
+        self.list(ast, "print", &[stmt.expression])
+    }
+
+    fn visit_var(&self, ast: &Ast, _: StmtId, stmt: &VarStmt) -> String {
+        let head = format!("var {}", stmt.name.lexeme);
+        self.list(ast, &head, stmt.initializer.as_slice())
+    }
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(v) => out.push_str(&v.to_string()),
+            Json::Number(v) => out.push_str(&v.to_string()),
+            Json::String(v) => {
+                out.push('"');
+                for c in v.chars() {
+                    match c {
+                        '"' => out.push_str("\\\""),
+                        '\\' => out.push_str("\\\\"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => out.push(c),
+                    }
+                }
+                out.push('"');
+            }
+            Json::Array(values) => {
+                out.push('[');
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(indent + 1));
+                    value.write(out, indent + 1);
+                }
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+                out.push(']');
+            }
+            Json::Object(fields) => {
+                out.push('{');
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(indent + 1));
+                    out.push_str(&format!("\"{key}\": "));
+                    value.write(out, indent + 1);
+                }
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+                out.push('}');
+            }
+        }
+    }
+}
+
+struct JsonWriter<'a> {
+    parser: &'a Parser,
+}
+
+impl JsonWriter<'_> {
+    fn node(&self, kind: &str, id: ExprId, fields: Vec<(&'static str, Json)>) -> Json {
+        Self::object(kind, self.parser.span(id), fields)
+    }
+
+    fn stmt(&self, kind: &str, id: StmtId, fields: Vec<(&'static str, Json)>) -> Json {
+        Self::object(kind, self.parser.stmt_span(id), fields)
+    }
+
+    fn object(kind: &str, span: Span, mut fields: Vec<(&'static str, Json)>) -> Json {
+        fields.insert(0, ("type", Json::String(kind.to_owned())));
+        let span = Json::Object(vec![
+            ("start_line", Json::Number(span.start_line as f64)),
+            ("start_column", Json::Number(span.start_column as f64)),
+            ("end_line", Json::Number(span.end_line as f64)),
+            ("end_column", Json::Number(span.end_column as f64)),
+        ]);
+        fields.insert(1, ("span", span));
+        Json::Object(fields)
+    }
+}
+
+impl ExprVisitor<Json> for JsonWriter<'_> {
+    fn visit_literal(&self, _: &Ast, id: ExprId, expr: &LiteralExpr) -> Json {
+        let value = match &expr.value {
+            LiteralValue::String(v) => Json::String(v.to_string()),
+            LiteralValue::Nubmer(v) => Json::Number(*v),
+            LiteralValue::Bool(v) => Json::Bool(*v),
+            LiteralValue::Nil => Json::Null,
+        };
+        self.node("Literal", id, vec![("value", value)])
+    }
+
+    fn visit_unary(&self, ast: &Ast, id: ExprId, expr: &UnaryExpr) -> Json {
+        self.node(
+            "Unary",
+            id,
+            vec![
+                ("operator", Json::String(expr.operator.lexeme.to_string())),
+                ("right", expr.right.visit(ast, self)),
+            ],
+        )
+    }
+
+    fn visit_binary(&self, ast: &Ast, id: ExprId, expr: &BinaryExpr) -> Json {
+        self.node(
+            "Binary",
+            id,
+            vec![
+                ("operator", Json::String(expr.operator.lexeme.to_string())),
+                ("left", expr.left.visit(ast, self)),
+                ("right", expr.right.visit(ast, self)),
+            ],
+        )
+    }
+
+    fn visit_grouping(&self, ast: &Ast, id: ExprId, expr: &GroupingExpr) -> Json {
+        self.node("Grouping", id, vec![("expression", expr.expression.visit(ast, self))])
+    }
+
+    fn visit_conditional(&self, ast: &Ast, id: ExprId, expr: &ConditionalExpr) -> Json {
+        self.node(
+            "Conditional",
+            id,
+            vec![
+                ("condition", expr.condition.visit(ast, self)),
+                ("then_branch", expr.then_branch.visit(ast, self)),
+                ("else_branch", expr.else_branch.visit(ast, self)),
+            ],
+        )
+    }
+
+    fn visit_variable(&self, _: &Ast, id: ExprId, expr: &VariableExpr) -> Json {
+        self.node("Variable", id, vec![("name", Json::String(expr.name.lexeme.to_string()))])
+    }
+
+    fn visit_assign(&self, ast: &Ast, id: ExprId, expr: &AssignExpr) -> Json {
+        self.node(
+            "Assign",
+            id,
+            vec![
+                ("name", Json::String(expr.name.lexeme.to_string())),
+                ("value", expr.value.visit(ast, self)),
+            ],
+        )
+    }
+
+    fn visit_postfix(&self, _: &Ast, id: ExprId, expr: &PostfixExpr) -> Json {
+        self.node(
+            "Postfix",
+            id,
+            vec![
+                ("name", Json::String(expr.name.lexeme.to_string())),
+                ("operator", Json::String(expr.operator.lexeme.to_string())),
+            ],
+        )
+    }
+}
+
+impl StmtVisitor<Json> for JsonWriter<'_> {
+    fn visit_expression(&self, ast: &Ast, id: StmtId, stmt: &ExpressionStmt) -> Json {
+        self.stmt("Expression", id, vec![("expression", stmt.expression.visit(ast, self))])
+    }
+
+    fn visit_print(&self, ast: &Ast, id: StmtId, stmt: &PrintStmt) -> Json {
+        self.stmt("Print", id, vec![("expression", stmt.expression.visit(ast, self))])
+    }
+
+    fn visit_var(&self, ast: &Ast, id: StmtId, stmt: &VarStmt) -> Json {
+        let initializer = match stmt.initializer {
+            Some(initializer) => initializer.visit(ast, self),
+            None => Json::Null,
+        };
+        self.stmt(
+            "Var",
+            id,
+            vec![
+                ("name", Json::String(stmt.name.lexeme.to_string())),
+                ("initializer", initializer),
+            ],
+        )
+    }
+}
+
+// reads the form written by `to_sexpr` into `ast`
+pub fn read_sexpr(source: &str, ast: &mut Ast) -> Result<ExprId, String> {
+    let mut reader = Reader {
+        chars: source.chars().peekable(),
+        ast,
+    };
+    let expr = reader.expr()?;
+    reader.skip_whitespace();
+    match reader.chars.next() {
+        Some(c) => Err(format!("unexpected '{c}' after expression")),
+        None => Ok(expr),
+    }
+}
+
+struct Reader<'a, 'b> {
+    chars: Peekable<Chars<'a>>,
+    ast: &'b mut Ast,
+}
+
+impl Reader<'_, '_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expr(&mut self) -> Result<ExprId, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('(') => {
+                self.chars.next();
+                self.list()
+            }
+            Some('"') => {
+                self.chars.next();
+                let mut v = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('"') => break,
+                        Some(c) => v.push(c),
+                        None => return Err("unterminated string".to_owned()),
+                    }
+                }
+                Ok(self.literal(LiteralValue::String(Symbol::intern(&v))))
+            }
+            Some(_) => {
+                let atom = self.atom();
+                let value = match atom.as_str() {
+                    "nil" => LiteralValue::Nil,
+                    "true" => LiteralValue::Bool(true),
+                    "false" => LiteralValue::Bool(false),
+                    _ if Self::is_name(&atom) => {
+                        return Ok(self.ast.add_expr(Expr::Variable(VariableExpr {
+                            name: Self::name(&atom),
+                        })));
+                    }
+                    _ => LiteralValue::Nubmer(atom.parse().map_err(|_| format!("unexpected '{atom}'"))?),
+                };
+                Ok(self.literal(value))
+            }
+            None => Err("unexpected end of input".to_owned()),
+        }
+    }
+
+    fn atom(&mut self) -> String {
+        let mut atom = String::new();
+        while let Some(c) = self.chars.next_if(|c| !c.is_whitespace() && *c != '(' && *c != ')') {
+            atom.push(c);
+        }
+        atom
+    }
+
+    // the rest of a list after its '('
+    fn list(&mut self) -> Result<ExprId, String> {
+        self.skip_whitespace();
+        let head = self.atom();
+        if let Some(lexeme @ ("++" | "--")) = head.strip_prefix("post") {
+            self.skip_whitespace();
+            let name = self.atom();
+            if !Self::is_name(&name) {
+                return Err(format!("unexpected '{name}' as {lexeme} target"));
+            }
+            self.skip_whitespace();
+            if self.chars.next() != Some(')') {
+                return Err(format!("expect ')' after {lexeme}"));
+            }
+            let token_type = if lexeme == "++" { TokenType::PlusPlus } else { TokenType::MinusMinus };
+            return Ok(self.ast.add_expr(Expr::Postfix(PostfixExpr {
+                name: Self::name(&name),
+                operator: Token::new_not_literal(token_type, lexeme, 0),
+            })));
+        }
+        // the target of `=` is a name, not an expression
+        if head == "=" {
+            self.skip_whitespace();
+            let name = self.atom();
+            if !Self::is_name(&name) {
+                return Err(format!("unexpected '{name}' as assignment target"));
+            }
+            let value = self.expr()?;
+            self.skip_whitespace();
+            if self.chars.next() != Some(')') {
+                return Err("expect ')' after assignment".to_owned());
+            }
+            return Ok(self.ast.add_expr(Expr::Assign(AssignExpr {
+                name: Self::name(&name),
+                value,
+            })));
+        }
+
+        let mut args = vec![];
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(')') => {
+                    self.chars.next();
+                    break;
+                }
+                Some(_) => args.push(self.expr()?),
+                None => return Err(format!("unclosed '({head}'")),
+            }
+        }
+
+        let expr = match (head.as_str(), args.as_slice()) {
+            ("Group", [expression]) => Expr::Grouping(GroupingExpr { expression: *expression }),
+            ("?:", [condition, then_branch, else_branch]) => Expr::Conditional(ConditionalExpr {
+                condition: *condition,
+                then_branch: *then_branch,
+                else_branch: *else_branch,
+            }),
+            ("-" | "!" | "~", [right]) => Expr::Unary(UnaryExpr {
+                operator: Self::operator(&head)?,
+                right: *right,
+            }),
+            (_, [left, right]) => Expr::Binary(BinaryExpr {
+                left: *left,
+                right: *right,
+                operator: Self::operator(&head)?,
+            }),
+            _ => return Err(format!("unexpected '({head}' with {} operands", args.len())),
+        };
+        Ok(self.ast.add_expr(expr))
+    }
+
+    fn is_name(atom: &str) -> bool {
+        atom.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && atom.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn name(atom: &str) -> Token {
+        Token::new_not_literal(TokenType::Identifier, atom, 0)
+    }
+
+    fn literal(&mut self, value: LiteralValue) -> ExprId {
+        self.ast.add_expr(Expr::Literal(LiteralExpr { value }))
+    }
+
+    fn operator(lexeme: &str) -> Result<Token, String> {
+        let token_type = match lexeme {
+            "," => TokenType::Comma,
+            "-" => TokenType::Minus,
+            "+" => TokenType::Plus,
+            "/" => TokenType::Slash,
+            "*" => TokenType::Star,
+            "%" => TokenType::Percent,
+            "&" => TokenType::Ampersand,
+            "|" => TokenType::Pipe,
+            "^" => TokenType::Caret,
+            "~" => TokenType::Tilde,
+            "==" => TokenType::EqualEqual,
+            "!" => TokenType::Bang,
+            "!=" => TokenType::BangEqual,
+            "<" => TokenType::Less,
+            "<=" => TokenType::LessEqual,
+            ">" => TokenType::Greater,
+            ">=" => TokenType::GreaterEqual,
+            "**" => TokenType::StarStar,
+            "//" => TokenType::SlashSlash,
+            "<<" => TokenType::LessLess,
+            ">>" => TokenType::GreaterGreater,
+            _ => return Err(format!("unknown operator '{lexeme}'")),
+        };
+        Ok(Token::new_not_literal(token_type, lexeme, 0))
+    }
+}
+
+#[test]
+fn sexpr_round_trip_test() {
+    use crate::scanner::Scanner;
+
+    let mut parser = Parser::new(Scanner::new("-(1 + \"a b\")//2 ? nil : !true, x = 3 ** ~y".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    let expected = "
+        (,
+            (?: (// (- (Group (+ 1 \"a b\"))) 2)
+                nil
+                (! true))
+            (= x (** 3 (~ y))))";
+
+    let mut ast = Ast::new();
+    let read = read_sexpr(expected, &mut ast).unwrap();
+    assert_eq!(to_sexpr(&ast, read), to_sexpr(&parser.ast, expr));
+    let read = read_sexpr("(post-- x)", &mut ast).unwrap();
+    assert_eq!("(post-- x)", to_sexpr(&ast, read));
+    assert_eq!(
+        Err("unknown operator '?'".to_owned()),
+        read_sexpr("(? 1 2)", &mut ast)
+    );
+}
+
+#[test]
+fn json_test() {
+    use crate::scanner::Scanner;
+
+    let mut parser = Parser::new(Scanner::new("1 +\n  (\"a\")".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    assert_eq!(
+        r#"{
+  "type": "Binary",
+  "span": {
+    "start_line": 1,
+    "start_column": 1,
+    "end_line": 2,
+    "end_column": 7
+  },
+  "operator": "+",
+  "left": {
+    "type": "Literal",
+    "span": {
+      "start_line": 1,
+      "start_column": 1,
+      "end_line": 1,
+      "end_column": 1
+    },
+    "value": 1
+  },
+  "right": {
+    "type": "Grouping",
+    "span": {
+      "start_line": 2,
+      "start_column": 3,
+      "end_line": 2,
+      "end_column": 7
+    },
+    "expression": {
+      "type": "Literal",
+      "span": {
+        "start_line": 2,
+        "start_column": 4,
+        "end_line": 2,
+        "end_column": 6
+      },
+      "value": "a"
+    }
+  }
+}"#,
+        to_json(&parser, expr)
+    );
+}
+
+#[test]
+fn tokens_test() {
+    use crate::scanner::Scanner;
+
+    let tokens = Scanner::new("x++ \"a\"\n  1.5".to_owned()).scan();
+    assert_eq!(
+        "1:1     Identifier      x\n1:2     PlusPlus        ++\n1:5     String          \"a\"             \"a\"\n2:3     Number          1.5             1.5\n2:6     Eof\n",
+        tokens_to_text(&tokens)
+    );
+    assert!(tokens_to_json(&tokens[..1]).contains("\"type\": \"Identifier\",\n    \"lexeme\": \"x\",\n    \"literal\": null"));
+}
+
+#[test]
+fn program_test() {
+    use crate::scanner::Scanner;
+
+    let mut parser = Parser::new(Scanner::new("var a = 1;\nvar b;\nprint a = 2;\nb;".to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    assert_eq!(
+        "(var a 1)\n(var b)\n(print (= a 2))\n(expression b)\n",
+        program_to_sexpr(&parser.ast, &stmts)
+    );
+    let json = program_to_json(&parser, &stmts);
+    assert!(json.contains("\"type\": \"Print\",\n    \"span\": {\n      \"start_line\": 3,\n      \"start_column\": 1,\n      \"end_line\": 3,\n      \"end_column\": 12\n    },"));
+}
@@ -1,38 +1,58 @@
 #![allow(unused_imports)]
 use crate::{
+    ast::Ast,
     expr::{
-        BinaryExpr, Expr, ExprVisitor, GroupingExpr, LiteralExpr, UnaryExpr, Visitor,
+        AssignExpr, BinaryExpr, ConditionalExpr, Expr, ExprId, ExprVisitor, GroupingExpr,
+        LiteralExpr, PostfixExpr, UnaryExpr, VariableExpr, Visitor,
     },
+    interpreter::{self, Value},
     token::{Token, TokenType, LiteralValue},
 };
+use std::{collections::HashMap, fmt::Display};
 
 pub struct PrintVisitor;
 impl ExprVisitor<String> for PrintVisitor {
-    fn visit_literal(&self, expr: &LiteralExpr) -> String {
+    fn visit_literal(&self, _: &Ast, _: ExprId, expr: &LiteralExpr) -> String {
         (match &expr.value {
-            LiteralValue::String(v) => v.to_owned(),
+            LiteralValue::String(v) => v.to_string(),
             LiteralValue::Nubmer(v) => v.to_string(),
             LiteralValue::Bool(v) => v.to_string(),
             LiteralValue::Nil => "nil".to_owned(),
         }) + " "
     }
 
-    fn visit_unary(&self, expr: &UnaryExpr) -> String {
-        expr.operator.lexeme.to_owned() + " " + &expr.right.visit(self)
+    fn visit_unary(&self, ast: &Ast, _: ExprId, expr: &UnaryExpr) -> String {
+        expr.operator.lexeme.to_string() + " " + &expr.right.visit(ast, self)
+    }
+
+    fn visit_binary(&self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> String {
+        expr.left.visit(ast, self) + &expr.operator.lexeme + " " + &expr.right.visit(ast, self)
+    }
+
+    fn visit_grouping(&self, ast: &Ast, _: ExprId, expr: &GroupingExpr) -> String {
+        "( ".to_owned() + &expr.expression.visit(ast, self) + ")" + " "
+    }
+
+    fn visit_conditional(&self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> String {
+        expr.condition.visit(ast, self) + "? " + &expr.then_branch.visit(ast, self) + ": " + &expr.else_branch.visit(ast, self)
+    }
+
+    fn visit_variable(&self, _: &Ast, _: ExprId, expr: &VariableExpr) -> String {
+        expr.name.lexeme.to_string() + " "
     }
 
-    fn visit_binary(&self, expr: &BinaryExpr) -> String {
-        expr.left.visit(self) + &expr.operator.lexeme + " " + &expr.right.visit(self)
+    fn visit_assign(&self, ast: &Ast, _: ExprId, expr: &AssignExpr) -> String {
+        expr.name.lexeme.to_string() + " = " + &expr.value.visit(ast, self)
     }
 
-    fn visit_grouping(&self, expr: &GroupingExpr) -> String {
-        "( ".to_owned() + &expr.expression.visit(self) + ")" + " "
+    fn visit_postfix(&self, _: &Ast, _: ExprId, expr: &PostfixExpr) -> String {
+        expr.name.lexeme.to_string() + &expr.operator.lexeme + " "
     }
 }
 
 pub struct AstVisitor;
 impl ExprVisitor<String> for AstVisitor {
-    fn visit_literal(&self, expr: &LiteralExpr) -> String {
+    fn visit_literal(&self, _: &Ast, _: ExprId, expr: &LiteralExpr) -> String {
         match &expr.value {
             LiteralValue::Nil => "nil".to_string(),
             LiteralValue::Bool(v) => v.to_string(),
@@ -41,28 +61,44 @@ impl ExprVisitor<String> for AstVisitor {
         }
     }
 
-    fn visit_unary(&self, expr: &UnaryExpr) -> String {
-        self.parenthesize(&expr.operator.lexeme, &[&expr.right])
+    fn visit_unary(&self, ast: &Ast, _: ExprId, expr: &UnaryExpr) -> String {
+        self.parenthesize(ast, &expr.operator.lexeme, &[expr.right])
+    }
+
+    fn visit_binary(&self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> String {
+        self.parenthesize(ast, &expr.operator.lexeme, &[expr.left, expr.right])
+    }
+
+    fn visit_grouping(&self, ast: &Ast, _: ExprId, expr: &GroupingExpr) -> String {
+        self.parenthesize(ast, "Group", &[expr.expression])
+    }
+
+    fn visit_conditional(&self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> String {
+        self.parenthesize(ast, "?:", &[expr.condition, expr.then_branch, expr.else_branch])
+    }
+
+    fn visit_variable(&self, _: &Ast, _: ExprId, expr: &VariableExpr) -> String {
+        expr.name.lexeme.to_string()
     }
 
-    fn visit_binary(&self, expr: &BinaryExpr) -> String {
-        self.parenthesize(&expr.operator.lexeme, &[&expr.left, &expr.right])
+    fn visit_assign(&self, ast: &Ast, _: ExprId, expr: &AssignExpr) -> String {
+        format!("(= {} {})", expr.name.lexeme, expr.value.visit(ast, self))
     }
 
-    fn visit_grouping(&self, expr: &GroupingExpr) -> String {
-        self.parenthesize("Group", &[&expr.expression])
+    fn visit_postfix(&self, _: &Ast, _: ExprId, expr: &PostfixExpr) -> String {
+        format!("(post{} {})", expr.operator.lexeme, expr.name.lexeme)
     }
 }
 
 impl AstVisitor {
     #[allow(dead_code)]
-    fn parenthesize(&self, lexeme: &str, exprs: &[&Expr]) -> String {
+    fn parenthesize(&self, ast: &Ast, lexeme: &str, exprs: &[ExprId]) -> String {
         let mut res = String::new();
         res.push('(');
         res.push_str(lexeme);
         for expr in exprs {
             res.push(' ');
-            res.push_str(&expr.visit(self));
+            res.push_str(&expr.visit(ast, self));
         }
         res.push(')');
 
@@ -70,97 +106,436 @@ impl AstVisitor {
     }
 }
 
+// postfix code for a stack machine, conditionals jump over the branch not taken
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+    Push(Value),
+    Pop,
+    // push the value of a global variable
+    Get(String),
+    // store the top of the stack in a global variable, leaving it there
+    Set(String),
+    Neg,
+    Not,
+    BitNot,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    FloorDiv,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    // skip the next n instructions
+    Jump(usize),
+    // pop the condition, skip the next n instructions if it is falsey
+    JumpIfFalse(usize),
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Instr::Push(Value::String(v)) => return write!(f, "\"{v}\""),
+            Instr::Push(v) => return write!(f, "{v}"),
+            Instr::Jump(n) => return write!(f, "jmp({n})"),
+            Instr::JumpIfFalse(n) => return write!(f, "jf({n})"),
+            Instr::Get(name) => return write!(f, "get({name})"),
+            Instr::Set(name) => return write!(f, "set({name})"),
+            Instr::Pop => "pop",
+            Instr::Neg => "neg",
+            Instr::Not => "!",
+            Instr::BitNot => "~",
+            Instr::Add => "+",
+            Instr::Sub => "-",
+            Instr::Mul => "*",
+            Instr::Div => "/",
+            Instr::Mod => "%",
+            Instr::FloorDiv => "//",
+            Instr::Pow => "**",
+            Instr::BitAnd => "&",
+            Instr::BitOr => "|",
+            Instr::BitXor => "^",
+            Instr::Shl => "<<",
+            Instr::Shr => ">>",
+            Instr::Equal => "==",
+            Instr::NotEqual => "!=",
+            Instr::Less => "<",
+            Instr::LessEqual => "<=",
+            Instr::Greater => ">",
+            Instr::GreaterEqual => ">=",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Instr {
+    // the token of an operator instruction
+    fn operator(&self) -> TokenType {
+        match self {
+            Instr::Neg | Instr::Sub => TokenType::Minus,
+            Instr::Not => TokenType::Bang,
+            Instr::BitNot => TokenType::Tilde,
+            Instr::Add => TokenType::Plus,
+            Instr::Mul => TokenType::Star,
+            Instr::Div => TokenType::Slash,
+            Instr::Mod => TokenType::Percent,
+            Instr::FloorDiv => TokenType::SlashSlash,
+            Instr::Pow => TokenType::StarStar,
+            Instr::BitAnd => TokenType::Ampersand,
+            Instr::BitOr => TokenType::Pipe,
+            Instr::BitXor => TokenType::Caret,
+            Instr::Shl => TokenType::LessLess,
+            Instr::Shr => TokenType::GreaterGreater,
+            Instr::Equal => TokenType::EqualEqual,
+            Instr::NotEqual => TokenType::BangEqual,
+            Instr::Less => TokenType::Less,
+            Instr::LessEqual => TokenType::LessEqual,
+            Instr::Greater => TokenType::Greater,
+            Instr::GreaterEqual => TokenType::GreaterEqual,
+            _ => unreachable!("not an operator {self:?}"),
+        }
+    }
+}
+
+// the code separated by spaces, e.g. `1 2 + 3 *`
+pub fn rpn(code: &[Instr]) -> String {
+    code.iter().map(Instr::to_string).collect::<Vec<_>>().join(" ")
+}
+
 pub struct RpnVisitor;
-impl ExprVisitor<String> for RpnVisitor {
-    fn visit_literal(&self, expr: &LiteralExpr) -> String {
-        (match &expr.value {
-            LiteralValue::String(s) => s.to_owned(),
-            LiteralValue::Nubmer(n) => n.to_string(),
-            LiteralValue::Bool(b) => b.to_string(),
-            LiteralValue::Nil => "nil".to_owned(),
-        }) + " "
+impl ExprVisitor<Vec<Instr>> for RpnVisitor {
+    fn visit_literal(&self, _: &Ast, _: ExprId, expr: &LiteralExpr) -> Vec<Instr> {
+        vec![Instr::Push(Value::from(&expr.value))]
     }
 
-    fn visit_unary(&self, expr: &UnaryExpr) -> String {
-        expr.right.visit(self) + &expr.operator.lexeme + " "
+    fn visit_unary(&self, ast: &Ast, _: ExprId, expr: &UnaryExpr) -> Vec<Instr> {
+        let mut code = expr.right.visit(ast, self);
+        code.push(match expr.operator.token_type {
+            TokenType::Minus => Instr::Neg,
+            TokenType::Bang => Instr::Not,
+            TokenType::Tilde => Instr::BitNot,
+            _ => unreachable!("unknown unary operator {:?}", expr.operator),
+        });
+        code
     }
 
-    fn visit_binary(&self, expr: &BinaryExpr) -> String {
-        expr.left.visit(self) + &expr.right.visit(self) + &expr.operator.lexeme + " "
+    fn visit_binary(&self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> Vec<Instr> {
+        let mut code = expr.left.visit(ast, self);
+        if expr.operator.token_type == TokenType::Comma {
+            code.push(Instr::Pop);
+            code.extend(expr.right.visit(ast, self));
+            return code;
+        }
+
+        code.extend(expr.right.visit(ast, self));
+        code.push(match expr.operator.token_type {
+            TokenType::Plus => Instr::Add,
+            TokenType::Minus => Instr::Sub,
+            TokenType::Star => Instr::Mul,
+            TokenType::Slash => Instr::Div,
+            TokenType::Percent => Instr::Mod,
+            TokenType::SlashSlash => Instr::FloorDiv,
+            TokenType::StarStar => Instr::Pow,
+            TokenType::Ampersand => Instr::BitAnd,
+            TokenType::Pipe => Instr::BitOr,
+            TokenType::Caret => Instr::BitXor,
+            TokenType::LessLess => Instr::Shl,
+            TokenType::GreaterGreater => Instr::Shr,
+            TokenType::EqualEqual => Instr::Equal,
+            TokenType::BangEqual => Instr::NotEqual,
+            TokenType::Less => Instr::Less,
+            TokenType::LessEqual => Instr::LessEqual,
+            TokenType::Greater => Instr::Greater,
+            TokenType::GreaterEqual => Instr::GreaterEqual,
+            _ => unreachable!("unknown binary operator {:?}", expr.operator),
+        });
+        code
     }
 
-    fn visit_grouping(&self, expr: &GroupingExpr) -> String {
-        expr.expression.visit(self)
+    fn visit_grouping(&self, ast: &Ast, _: ExprId, expr: &GroupingExpr) -> Vec<Instr> {
+        expr.expression.visit(ast, self)
+    }
+
+    fn visit_conditional(&self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> Vec<Instr> {
+        let then_branch = expr.then_branch.visit(ast, self);
+        let else_branch = expr.else_branch.visit(ast, self);
+        let mut code = expr.condition.visit(ast, self);
+        code.push(Instr::JumpIfFalse(then_branch.len() + 1));
+        code.extend(then_branch);
+        code.push(Instr::Jump(else_branch.len()));
+        code.extend(else_branch);
+        code
+    }
+
+    fn visit_variable(&self, _: &Ast, _: ExprId, expr: &VariableExpr) -> Vec<Instr> {
+        vec![Instr::Get(expr.name.lexeme.to_string())]
+    }
+
+    fn visit_assign(&self, ast: &Ast, _: ExprId, expr: &AssignExpr) -> Vec<Instr> {
+        let mut code = expr.value.visit(ast, self);
+        code.push(Instr::Set(expr.name.lexeme.to_string()));
+        code
+    }
+
+    // the old value stays below the new one, which is set and dropped
+    fn visit_postfix(&self, _: &Ast, _: ExprId, expr: &PostfixExpr) -> Vec<Instr> {
+        let name = expr.name.lexeme.to_string();
+        let update = if expr.operator.token_type == TokenType::PlusPlus { Instr::Add } else { Instr::Sub };
+        vec![
+            Instr::Get(name.clone()),
+            Instr::Get(name.clone()),
+            Instr::Push(Value::Number(1.0)),
+            update,
+            Instr::Set(name),
+            Instr::Pop,
+        ]
     }
 }
 
+// runs code from `RpnVisitor`, the operators are the interpreter's own
+pub fn run(code: &[Instr], globals: &mut HashMap<String, Value>) -> Result<Value, String> {
+    let mut stack = vec![];
+    let mut ip = 0;
+    while let Some(instr) = code.get(ip) {
+        ip += 1;
+        match instr {
+            Instr::Push(v) => stack.push(v.clone()),
+            Instr::Pop => {
+                stack.pop();
+            }
+            Instr::Get(name) => match globals.get(name) {
+                Some(v) => stack.push(v.clone()),
+                None => return Err(format!("Undefined variable '{name}'.")),
+            },
+            Instr::Set(name) => match globals.get_mut(name) {
+                Some(v) => *v = stack.last().expect("stack underflow").clone(),
+                None => return Err(format!("Undefined variable '{name}'.")),
+            },
+            Instr::Jump(n) => ip += n,
+            Instr::JumpIfFalse(n) => {
+                if !pop(&mut stack).is_truthy() {
+                    ip += n;
+                }
+            }
+            Instr::Neg | Instr::Not | Instr::BitNot => {
+                let v = pop(&mut stack);
+                stack.push(interpreter::unary(&instr.operator(), &v)?);
+            }
+            _ => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                stack.push(interpreter::binary(&instr.operator(), left, right)?);
+            }
+        }
+    }
+
+    Ok(pop(&mut stack))
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("stack underflow")
+}
+
 #[test]
 fn print_visitor_test() {
+    let mut ast = Ast::new();
     // 1
-    let one = Expr::Literal(Box::new(LiteralExpr {
+    let one = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(1.0),
     }));
     // 2
-    let two = Expr::Literal(Box::new(LiteralExpr {
+    let two = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(2.0),
     }));
     // 1 + 2
-    let b = Expr::Binary(Box::new(BinaryExpr {
+    let b = ast.add_expr(Expr::Binary(BinaryExpr {
         left: one,
         right: two,
-        operator: Token::new_not_literal(TokenType::Plus, "+".to_owned(), 1),
+        operator: Token::new_not_literal(TokenType::Plus, "+", 1),
     }));
     // (1 + 2)
-    let g = Expr::Grouping(Box::new(GroupingExpr { expression: b }));
+    let g = ast.add_expr(Expr::Grouping(GroupingExpr { expression: b }));
     // - (1 + 2)
-    let u = Expr::Unary(Box::new(UnaryExpr {
-        operator: Token::new_not_literal(TokenType::Minus, "-".to_owned(), 1),
+    let u = ast.add_expr(Expr::Unary(UnaryExpr {
+        operator: Token::new_not_literal(TokenType::Minus, "-", 1),
         right: g,
     }));
 
-    println!("{}", u.visit(&PrintVisitor));
+    println!("{}", u.visit(&ast, &PrintVisitor));
 }
 
 #[test]
 fn rpn_test() {
+    let mut ast = Ast::new();
     // 1
-    let one = Expr::Literal(Box::new(LiteralExpr {
+    let one = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(1.0),
     }));
     // 2
-    let two = Expr::Literal(Box::new(LiteralExpr {
+    let two = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(2.0),
     }));
     // 3
-    let three = Expr::Literal(Box::new(LiteralExpr {
+    let three = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(3.0),
     }));
     // 4
-    let four = Expr::Literal(Box::new(LiteralExpr {
+    let four = ast.add_expr(Expr::Literal(LiteralExpr {
         value: LiteralValue::Nubmer(4.0),
     }));
     // (1 + 2)
-    let a = Expr::Grouping(Box::new(GroupingExpr {
-        expression: Expr::Binary(Box::new(BinaryExpr {
-            left: one,
-            right: two,
-            operator: Token::new_not_literal(TokenType::Plus, "+".to_owned(), 1),
-        })),
+    let a = ast.add_expr(Expr::Binary(BinaryExpr {
+        left: one,
+        right: two,
+        operator: Token::new_not_literal(TokenType::Plus, "+", 1),
     }));
+    let a = ast.add_expr(Expr::Grouping(GroupingExpr { expression: a }));
     // (4 - 3)
-    let b = Expr::Grouping(Box::new(GroupingExpr {
-        expression: Expr::Binary(Box::new(BinaryExpr {
-            left: four,
-            right: three,
-            operator: Token::new_not_literal(TokenType::Plus, "-".to_owned(), 1),
-        })),
+    let b = ast.add_expr(Expr::Binary(BinaryExpr {
+        left: four,
+        right: three,
+        operator: Token::new_not_literal(TokenType::Minus, "-", 1),
     }));
+    let b = ast.add_expr(Expr::Grouping(GroupingExpr { expression: b }));
     // (1 + 2) * (4 - 3)
-    let c = Expr::Binary(Box::new(BinaryExpr {
+    let c = ast.add_expr(Expr::Binary(BinaryExpr {
         left: a,
         right: b,
-        operator: Token::new_not_literal(TokenType::Star, "*".to_owned(), 1),
+        operator: Token::new_not_literal(TokenType::Star, "*", 1),
     }));
 
-    println!("{}", c.visit(&RpnVisitor));
+    let code = c.visit(&ast, &RpnVisitor);
+    assert_eq!("1 2 + 4 3 - *", rpn(&code));
+    assert_eq!(Ok(Value::Number(3.0)), run(&code, &mut HashMap::new()));
+}
+
+#[test]
+fn rpn_conditional_test() {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    let mut parser = Parser::new(Scanner::new("-1 > 0 ? 1//0 : (2, \"a\")".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    let code = expr.visit(&parser.ast, &RpnVisitor);
+    assert_eq!("1 neg 0 > jf(4) 1 0 // jmp(3) 2 pop \"a\"", rpn(&code));
+    assert_eq!(Ok(Value::String("a".to_owned())), run(&code, &mut HashMap::new()));
+
+    let mut parser = Parser::new(Scanner::new("a = b + 1".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    let code = expr.visit(&parser.ast, &RpnVisitor);
+    assert_eq!("get(b) 1 + set(a)", rpn(&code));
+    let mut globals = HashMap::from([("a".to_owned(), Value::Nil), ("b".to_owned(), Value::Number(1.0))]);
+    assert_eq!(Ok(Value::Number(2.0)), run(&code, &mut globals));
+    assert_eq!(Some(&Value::Number(2.0)), globals.get("a"));
+}
+
+// xorshift for the random tests, seeded by them so failures reproduce
+#[cfg(test)]
+pub(crate) struct Rng(pub u64);
+
+#[cfg(test)]
+impl Rng {
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+// a random tree of literals and operators, for checking backends against each other
+#[cfg(test)]
+pub(crate) fn random_expr(rng: &mut Rng, ast: &mut Ast, depth: u32) -> ExprId {
+    const LITERALS: [LiteralValue; 8] = [
+        LiteralValue::Nubmer(0.0),
+        LiteralValue::Nubmer(1.0),
+        LiteralValue::Nubmer(2.0),
+        LiteralValue::Nubmer(-3.0),
+        LiteralValue::Nubmer(0.5),
+        LiteralValue::Bool(true),
+        LiteralValue::Bool(false),
+        LiteralValue::Nil,
+    ];
+    const UNARY: [(TokenType, &str); 3] = [(TokenType::Minus, "-"), (TokenType::Bang, "!"), (TokenType::Tilde, "~")];
+    const BINARY: [(TokenType, &str); 19] = [
+        (TokenType::Comma, ","),
+        (TokenType::Plus, "+"),
+        (TokenType::Minus, "-"),
+        (TokenType::Star, "*"),
+        (TokenType::Slash, "/"),
+        (TokenType::Percent, "%"),
+        (TokenType::SlashSlash, "//"),
+        (TokenType::StarStar, "**"),
+        (TokenType::Ampersand, "&"),
+        (TokenType::Pipe, "|"),
+        (TokenType::Caret, "^"),
+        (TokenType::LessLess, "<<"),
+        (TokenType::GreaterGreater, ">>"),
+        (TokenType::EqualEqual, "=="),
+        (TokenType::BangEqual, "!="),
+        (TokenType::Less, "<"),
+        (TokenType::LessEqual, "<="),
+        (TokenType::Greater, ">"),
+        (TokenType::GreaterEqual, ">="),
+    ];
+    let token = |(token_type, lexeme): &(TokenType, &str)| Token::new_not_literal(token_type.clone(), lexeme, 1);
+
+    let kind = if depth == 0 { 0 } else { rng.below(6) };
+    let expr = match kind {
+        0 | 1 if rng.below(8) == 0 => Expr::Literal(LiteralExpr {
+            value: LiteralValue::String(["a", "b"][rng.below(2)].into()),
+        }),
+        0 | 1 => Expr::Literal(LiteralExpr {
+            value: LITERALS[rng.below(LITERALS.len())].clone(),
+        }),
+        2 => Expr::Unary(UnaryExpr {
+            operator: token(&UNARY[rng.below(UNARY.len())]),
+            right: random_expr(rng, ast, depth - 1),
+        }),
+        3 => Expr::Grouping(GroupingExpr {
+            expression: random_expr(rng, ast, depth - 1),
+        }),
+        4 => Expr::Conditional(ConditionalExpr {
+            condition: random_expr(rng, ast, depth - 1),
+            then_branch: random_expr(rng, ast, depth - 1),
+            else_branch: random_expr(rng, ast, depth - 1),
+        }),
+        _ => Expr::Binary(BinaryExpr {
+            left: random_expr(rng, ast, depth - 1),
+            right: random_expr(rng, ast, depth - 1),
+            operator: token(&BINARY[rng.below(BINARY.len())]),
+        }),
+    };
+    ast.add_expr(expr)
+}
+
+// the operators are shared, so random trees check that the translation keeps the order of
+// evaluation, the jumps and the errors of the tree-walking interpreter
+#[test]
+fn rpn_matches_interpreter_test() {
+    use crate::interpreter::Interpreter;
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..2000 {
+        let mut ast = Ast::new();
+        let expr = random_expr(&mut rng, &mut ast, 5);
+        let expected = Interpreter::default().interpret(&ast, expr).map_err(|err| err.message);
+        let actual = run(&expr.visit(&ast, &RpnVisitor), &mut HashMap::new());
+        // compare through Debug so NaN equals NaN
+        assert_eq!(
+            format!("{expected:?}"),
+            format!("{actual:?}"),
+            "{}",
+            expr.visit(&ast, &AstVisitor)
+        );
+    }
 }
@@ -1,56 +1,105 @@
 #![allow(dead_code)]
-use crate::token::{LiteralValue, Token};
+use crate::{
+    ast::Ast,
+    token::{LiteralValue, Token},
+};
+use lox_ast_derive::ast;
 
-pub trait Visitor<R> {
-    fn visit(&self, expr: &Expr) -> R;
-}
-pub trait ExprVisitor<R> {
-    fn visit_literal(&self, expr: &LiteralExpr) -> R;
-    fn visit_unary(&self, expr: &UnaryExpr) -> R;
-    fn visit_binary(&self, expr: &BinaryExpr) -> R;
-    fn visit_grouping(&self, expr: &GroupingExpr) -> R;
+#[ast(arena = Ast)]
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal { value: LiteralValue },
+    Unary { operator: Token, right: ExprId },
+    Binary { left: ExprId, right: ExprId, operator: Token },
+    Grouping { expression: ExprId },
+    Conditional { condition: ExprId, then_branch: ExprId, else_branch: ExprId },
+    Variable { name: Token },
+    Assign { name: Token, value: ExprId },
+    // `x++` and `x--`, which evaluate to the value before the update
+    Postfix { name: Token, operator: Token },
 }
-impl<T, R> Visitor<R> for T
-where
-    T: ExprVisitor<R>,
-{
-    fn visit(&self, expr: &Expr) -> R {
-        match expr {
-            Expr::Literal(v) => self.visit_literal(v),
-            Expr::Unary(v) => self.visit_unary(v),
-            Expr::Binary(v) => self.visit_binary(v),
-            Expr::Grouping(v) => self.visit_grouping(v),
+
+#[test]
+fn visitor_mut_test() {
+    use crate::{parser::Parser, scanner::Scanner};
+    use std::collections::HashMap;
+
+    // side table keyed by node id: the depth of every literal
+    struct LiteralDepth {
+        depth: usize,
+        depths: HashMap<ExprId, usize>,
+    }
+    impl ExprVisitorMut<()> for LiteralDepth {
+        fn visit_literal(&mut self, _: &Ast, id: ExprId, _: &LiteralExpr) {
+            self.depths.insert(id, self.depth);
+        }
+        fn visit_unary(&mut self, ast: &Ast, _: ExprId, expr: &UnaryExpr) {
+            self.depth += 1;
+            walk_unary(self, ast, expr);
+            self.depth -= 1;
         }
+        fn visit_grouping(&mut self, ast: &Ast, _: ExprId, expr: &GroupingExpr) {
+            self.depth += 1;
+            walk_grouping(self, ast, expr);
+            self.depth -= 1;
+        }
+        fn visit_conditional(&mut self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) {
+            self.depth += 1;
+            walk_conditional(self, ast, expr);
+            self.depth -= 1;
+        }
+        // binary and the rest walk without counting
     }
+
+    let mut parser = Parser::new(Scanner::new("1 ? -(2 + 3) : 4".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    let mut pass = LiteralDepth {
+        depth: 0,
+        depths: HashMap::new(),
+    };
+    pass.visit_expr(&parser.ast, expr);
+
+    let mut depths = pass.depths.into_iter().collect::<Vec<_>>();
+    depths.sort();
+    let depths = depths.into_iter().map(|(_, depth)| depth).collect::<Vec<_>>();
+    assert_eq!(vec![1, 3, 3, 1], depths);
 }
-#[derive(Debug)]
-pub enum Expr {
-    Literal(Box<LiteralExpr>),
-    Unary(Box<UnaryExpr>),
-    Binary(Box<BinaryExpr>),
-    Grouping(Box<GroupingExpr>),
-}
-impl Expr {
-    pub fn visit<R>(&self, visitor: &dyn Visitor<R>) -> R {
-        visitor.visit(self)
+
+#[test]
+fn mut_visitor_and_fold_test() {
+    use crate::{exercise::ch5::AstVisitor, parser::Parser, scanner::Scanner};
+
+    // doubles every number in place
+    struct Doubler;
+    impl MutVisitor for Doubler {
+        fn visit_literal_mut(&mut self, ast: &mut Ast, expr: ExprId) {
+            if let Expr::Literal(LiteralExpr {
+                value: LiteralValue::Nubmer(v),
+            }) = &mut ast[expr]
+            {
+                *v *= 2.0;
+            }
+        }
     }
-}
-#[derive(Debug)]
-pub struct LiteralExpr {
-    pub value: LiteralValue,
-}
-#[derive(Debug)]
-pub struct UnaryExpr {
-    pub operator: Token,
-    pub right: Expr,
-}
-#[derive(Debug)]
-pub struct BinaryExpr {
-    pub left: Expr,
-    pub right: Expr,
-    pub operator: Token,
-}
-#[derive(Debug)]
-pub struct GroupingExpr {
-    pub expression: Expr,
+
+    // removes groupings
+    struct Ungroup;
+    impl Fold for Ungroup {
+        fn fold_grouping(&mut self, ast: &Ast, out: &mut Ast, expr: &GroupingExpr) -> ExprId {
+            self.fold_expr(ast, out, expr.expression)
+        }
+    }
+
+    let mut parser = Parser::new(Scanner::new("-(1 + (2)) * 3".to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    let mut ast = parser.ast.clone();
+    Doubler.visit_expr_mut(&mut ast, expr);
+    assert_eq!("(* (- (Group (+ 2 (Group 4)))) 6)", expr.visit(&ast, &AstVisitor));
+    // the clone is untouched
+    assert_eq!("(* (- (Group (+ 1 (Group 2)))) 3)", expr.visit(&parser.ast, &AstVisitor));
+
+    let mut out = Ast::new();
+    let expr = Ungroup.fold_expr(&ast, &mut out, expr);
+    assert_eq!("(* (- (+ 2 4)) 6)", expr.visit(&out, &AstVisitor));
+    assert_eq!(6, out.expr_count());
 }
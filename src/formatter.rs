@@ -0,0 +1,435 @@
+use std::ops::Range;
+
+use crate::{
+    ast::Ast,
+    expr::{
+        AssignExpr, BinaryExpr, ConditionalExpr, Expr, ExprId, ExprVisitorMut, GroupingExpr,
+        LiteralExpr, PostfixExpr, UnaryExpr, VariableExpr,
+    },
+    parser::Parser,
+    runner,
+    scanner::{Comment, Scanner},
+    stmt::{ExpressionStmt, PrintStmt, StmtId, StmtVisitorMut, VarStmt},
+    token::{LiteralValue, Token, TokenType},
+};
+
+const WIDTH: usize = 80;
+const INDENT: usize = 4;
+
+// layout of the output, groups are printed on one line when they fit and broken otherwise
+#[derive(Debug)]
+enum Doc {
+    Text(String),
+    // a space, or a newline when the enclosing group breaks
+    Line,
+    // nothing, or a newline when the enclosing group breaks
+    SoftLine,
+    // always a newline, forces the enclosing groups to break
+    HardLine,
+    // a space unless at the start of a line or after one, so a comment is never glued to a token
+    Gap,
+    Nest(Vec<Doc>),
+    Group(Vec<Doc>),
+}
+
+// nothing, what a visit gives when it is not overridden
+impl Default for Doc {
+    fn default() -> Self {
+        Doc::Group(vec![])
+    }
+}
+
+fn text(s: &str) -> Doc {
+    Doc::Text(s.to_owned())
+}
+
+// formats a whole program, `None` if it has errors, which are reported already
+pub fn format(source: &str) -> Option<String> {
+    runner::reset_error();
+    let (tokens, comments) = Scanner::new(source.to_owned()).scan_with_comments();
+    let mut parser = Parser::new(tokens);
+    let stmts = parser.parse_program();
+    if runner::had_error() {
+        return None;
+    }
+    let stmts = stmts?;
+
+    let mut formatter = Formatter {
+        tokens: &parser.tokens,
+        spans: &parser.spans,
+        stmt_spans: &parser.stmt_spans,
+        comments,
+        next_comment: 0,
+    };
+    let mut docs = vec![];
+    let mut end = 0;
+    for stmt in stmts {
+        let span = parser.stmt_spans[stmt.index()].clone();
+        if end > 0 {
+            docs.push(Doc::HardLine);
+            // keep one blank line where the source had some, a comment starts the statement
+            let start = match formatter.comments.get(formatter.next_comment) {
+                Some(comment) if comment.token_index <= span.start => comment.line,
+                _ => parser.tokens[span.start].line,
+            };
+            if start > parser.tokens[end - 1].end().0 + 1 {
+                docs.push(Doc::HardLine);
+            }
+        }
+        // outside of the statement's group, a comment on its own line would break it
+        docs.extend(formatter.comments_before(span.start));
+        docs.push(formatter.visit_stmt(&parser.ast, stmt));
+        end = span.end;
+    }
+    docs.extend(formatter.trailing_comments(end));
+    docs.push(Doc::HardLine);
+
+    let mut printer = Printer::default();
+    printer.print(&docs, 0, false);
+    Some(printer.out)
+}
+
+struct Formatter<'a> {
+    tokens: &'a [Token],
+    spans: &'a [Range<usize>],
+    stmt_spans: &'a [Range<usize>],
+    comments: Vec<Comment>,
+    next_comment: usize,
+}
+
+impl Formatter<'_> {
+    // the comments before the token at `index` which are not printed yet
+    fn comments_before(&mut self, index: usize) -> Vec<Doc> {
+        let mut docs = vec![];
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index > index {
+                break;
+            }
+            self.next_comment += 1;
+            docs.push(Doc::Gap);
+            docs.push(text(&comment.text));
+            // keep a comment on its own line if the following token was on a later line
+            let end_line = comment.line + comment.text.matches('\n').count() as u32;
+            if comment.text.starts_with("//") || self.tokens[comment.token_index].line > end_line {
+                docs.push(Doc::HardLine);
+            } else {
+                docs.push(text(" "));
+            }
+        }
+
+        docs
+    }
+
+    // the comments after the last token, on the same line if they were
+    fn trailing_comments(&mut self, end: usize) -> Vec<Doc> {
+        let mut docs = vec![];
+        let mut line = self.tokens[end.saturating_sub(1)].line;
+        for comment in &self.comments[self.next_comment..] {
+            if comment.line == line {
+                docs.push(text(" "));
+            } else {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(text(&comment.text));
+            line = comment.line + comment.text.matches('\n').count() as u32;
+        }
+        self.next_comment = self.comments.len();
+
+        docs
+    }
+
+    // a statement ended by the `;` at `index`, the comments before the `;` and those after it
+    // on its line, up to the next statement, follow it: none ends up before the `;`
+    fn statement(&mut self, mut docs: Vec<Doc>, index: usize) -> Doc {
+        docs.push(text(";"));
+        let mut docs = vec![Doc::Group(docs)];
+        let line = self.tokens[index].line;
+        let mut line_comment = false;
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.token_index > index + 1 || (comment.token_index > index && comment.line != line) {
+                break;
+            }
+            self.next_comment += 1;
+            docs.push(if line_comment { Doc::HardLine } else { text(" ") });
+            docs.push(text(&comment.text));
+            line_comment = comment.text.starts_with("//");
+        }
+
+        Doc::Group(docs)
+    }
+
+    // a token printed as is, with the comments in front of it
+    fn token(&mut self, index: usize, lexeme: &str) -> Vec<Doc> {
+        let mut docs = self.comments_before(index);
+        docs.push(text(lexeme));
+        docs
+    }
+}
+
+impl ExprVisitorMut<Doc> for Formatter<'_> {
+    fn visit_literal(&mut self, _: &Ast, id: ExprId, expr: &LiteralExpr) -> Doc {
+        let lexeme = match &expr.value {
+            LiteralValue::String(v) => format!("\"{v}\""),
+            LiteralValue::Nubmer(v) => v.to_string(),
+            LiteralValue::Bool(v) => v.to_string(),
+            LiteralValue::Nil => "nil".to_owned(),
+        };
+        Doc::Group(self.token(self.spans[id.index()].start, &lexeme))
+    }
+
+    fn visit_unary(&mut self, ast: &Ast, id: ExprId, expr: &UnaryExpr) -> Doc {
+        let mut docs = self.token(self.spans[id.index()].start, &expr.operator.lexeme);
+        // `- -1` must not become `--1`, nor `- --x` become `---x`
+        let first = &self.tokens[self.spans[expr.right.index()].start];
+        if expr.operator.token_type == TokenType::Minus && first.lexeme.starts_with('-') {
+            docs.push(text(" "));
+        }
+        docs.push(self.visit_expr(ast, expr.right));
+        Doc::Group(docs)
+    }
+
+    fn visit_binary(&mut self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> Doc {
+        let left = self.visit_expr(ast, expr.left);
+        let operator = self.token(self.spans[expr.left.index()].end, &expr.operator.lexeme);
+        let right = self.visit_expr(ast, expr.right);
+
+        // with spaces around it `//` would start a comment
+        if expr.operator.token_type == TokenType::SlashSlash {
+            let mut docs = vec![left];
+            docs.extend(operator);
+            docs.push(right);
+            return Doc::Group(docs);
+        }
+
+        if expr.operator.token_type == TokenType::Comma {
+            let mut docs = vec![left];
+            docs.extend(operator);
+            docs.push(Doc::Nest(vec![Doc::Line, right]));
+            return Doc::Group(docs);
+        }
+
+        // long expressions break before the operator
+        let mut rest = vec![Doc::Line];
+        rest.extend(operator);
+        rest.push(text(" "));
+        rest.push(right);
+        Doc::Group(vec![left, Doc::Nest(rest)])
+    }
+
+    fn visit_grouping(&mut self, ast: &Ast, id: ExprId, expr: &GroupingExpr) -> Doc {
+        let span = self.spans[id.index()].clone();
+        let mut docs = self.token(span.start, "(");
+        docs.push(Doc::Nest(vec![Doc::SoftLine, self.visit_expr(ast, expr.expression)]));
+        docs.push(Doc::SoftLine);
+        docs.extend(self.token(span.end - 1, ")"));
+        Doc::Group(docs)
+    }
+
+    fn visit_conditional(&mut self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> Doc {
+        let condition = self.visit_expr(ast, expr.condition);
+        let mut rest = vec![Doc::Line];
+        rest.extend(self.token(self.spans[expr.condition.index()].end, "?"));
+        rest.push(text(" "));
+        rest.push(self.visit_expr(ast, expr.then_branch));
+        rest.push(Doc::Line);
+        rest.extend(self.token(self.spans[expr.then_branch.index()].end, ":"));
+        rest.push(text(" "));
+        rest.push(self.visit_expr(ast, expr.else_branch));
+        Doc::Group(vec![condition, Doc::Nest(rest)])
+    }
+
+    fn visit_variable(&mut self, _: &Ast, id: ExprId, expr: &VariableExpr) -> Doc {
+        Doc::Group(self.token(self.spans[id.index()].start, &expr.name.lexeme))
+    }
+
+    // `x += v` and `++x` are parsed as `x = x + ...`, the tokens tell which was written
+    fn visit_assign(&mut self, ast: &Ast, id: ExprId, expr: &AssignExpr) -> Doc {
+        let start = self.spans[id.index()].start;
+        let first = self.tokens[start].token_type.clone();
+        if matches!(first, TokenType::PlusPlus | TokenType::MinusMinus) {
+            let mut docs = self.token(start, &self.tokens[start].lexeme);
+            docs.extend(self.token(start + 1, &expr.name.lexeme));
+            return Doc::Group(docs);
+        }
+
+        let mut docs = self.token(start, &expr.name.lexeme);
+        let operator = self.tokens[start + 1].clone();
+        let value = match (&operator.token_type, &ast[expr.value]) {
+            (TokenType::Equal, _) => expr.value,
+            (_, Expr::Binary(binary)) => binary.right,
+            _ => unreachable!("a compound assignment is parsed to a binary"),
+        };
+        docs.push(text(" "));
+        docs.extend(self.token(start + 1, &operator.lexeme));
+        docs.push(Doc::Nest(vec![Doc::Line, self.visit_expr(ast, value)]));
+        Doc::Group(docs)
+    }
+
+    fn visit_postfix(&mut self, _: &Ast, id: ExprId, expr: &PostfixExpr) -> Doc {
+        let start = self.spans[id.index()].start;
+        let mut docs = self.token(start, &expr.name.lexeme);
+        docs.extend(self.token(start + 1, &expr.operator.lexeme));
+        Doc::Group(docs)
+    }
+}
+
+impl StmtVisitorMut<Doc> for Formatter<'_> {
+    fn visit_expression(&mut self, ast: &Ast, id: StmtId, stmt: &ExpressionStmt) -> Doc {
+        let docs = vec![self.visit_expr(ast, stmt.expression)];
+        self.statement(docs, self.stmt_spans[id.index()].end - 1)
+    }
+
+    fn visit_print(&mut self, ast: &Ast, id: StmtId, stmt: &PrintStmt) -> Doc {
+        let span = self.stmt_spans[id.index()].clone();
+        let mut docs = self.token(span.start, "print");
+        docs.push(text(" "));
+        docs.push(self.visit_expr(ast, stmt.expression));
+        self.statement(docs, span.end - 1)
+    }
+
+    fn visit_var(&mut self, ast: &Ast, id: StmtId, stmt: &VarStmt) -> Doc {
+        let span = self.stmt_spans[id.index()].clone();
+        let mut docs = self.token(span.start, "var");
+        docs.push(text(" "));
+        docs.extend(self.token(span.start + 1, &stmt.name.lexeme));
+        if let Some(initializer) = stmt.initializer {
+            docs.push(text(" "));
+            docs.extend(self.token(span.start + 2, "="));
+            docs.push(Doc::Nest(vec![Doc::Line, self.visit_expr(ast, initializer)]));
+        }
+        self.statement(docs, span.end - 1)
+    }
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    column: usize,
+}
+
+impl Printer {
+    fn print(&mut self, docs: &[Doc], indent: usize, flat: bool) {
+        for doc in docs {
+            match doc {
+                Doc::Text(s) => {
+                    self.out.push_str(s);
+                    match s.rfind('\n') {
+                        Some(i) => self.column = s[i + 1..].chars().count(),
+                        None => self.column += s.chars().count(),
+                    }
+                }
+                Doc::Line if flat => self.text(" "),
+                Doc::SoftLine if flat => (),
+                Doc::Line | Doc::SoftLine | Doc::HardLine => self.newline(indent),
+                Doc::Gap if self.out.is_empty() || self.out.ends_with([' ', '\n']) => (),
+                Doc::Gap => self.text(" "),
+                Doc::Nest(docs) => self.print(docs, indent + INDENT, flat),
+                Doc::Group(docs) => {
+                    let flat = flat || Self::fits(docs, WIDTH.saturating_sub(self.column) as isize) >= 0;
+                    self.print(docs, indent, flat)
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, s: &str) {
+        self.out.push_str(s);
+        self.column += s.chars().count();
+    }
+
+    fn newline(&mut self, indent: usize) {
+        // no trailing whitespace on blank lines
+        while self.out.ends_with(' ') {
+            self.out.pop();
+        }
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.column = indent;
+    }
+
+    // the width left after printing `docs` on one line, negative if they don't fit
+    fn fits(docs: &[Doc], mut remaining: isize) -> isize {
+        for doc in docs {
+            if remaining < 0 {
+                break;
+            }
+            remaining = match doc {
+                Doc::Text(s) if s.contains('\n') => -1,
+                Doc::Text(s) => remaining - s.chars().count() as isize,
+                Doc::Line | Doc::Gap => remaining - 1,
+                Doc::SoftLine => remaining,
+                Doc::HardLine => -1,
+                Doc::Nest(docs) | Doc::Group(docs) => Self::fits(docs, remaining),
+            };
+        }
+
+        remaining
+    }
+}
+
+#[test]
+fn format_test() {
+    assert_eq!("1 + 2 * (3 - -4);\n", format("1+2*( 3- -4 );").unwrap());
+    assert_eq!("- -1;\n", format("- -1;").unwrap());
+    assert_eq!(
+        "var x;\nx += 1 * 2;\nx++;\n--x;\nprint x /= 2;\n",
+        format("var x;x+=1*2;x ++;-- x;print x/=2;").unwrap()
+    );
+    assert_eq!("var a = (1 + 2)//-3; // c\n", format("var a = (1+2)//-3 // c\n;").unwrap());
+    assert_eq!("1 ? \"a\" : nil, true;\n", format("1?\"a\":nil,true;").unwrap());
+    assert_eq!(
+        "var a = 1;\nvar b;\n\nprint a = b;\n",
+        format("var a=1;var b;\n\n\nprint a=b;").unwrap()
+    );
+}
+
+#[test]
+fn format_comment_test() {
+    let source = "// leading\n1 /* one */ + // two\n2; // trailing\n";
+    let formatted = format(source).unwrap();
+    assert_eq!("// leading\n1\n    /* one */ + // two\n    2; // trailing\n", formatted);
+    assert_eq!(formatted, format(&formatted).unwrap());
+}
+
+#[test]
+fn format_comment_position_test() {
+    let source = "var a = 1\n// before\n;\nvar b = (a /* in */) // after\n;\na; /* same line */\nb = b /* x */ ; // y\n/* own line */\na = -a;\nvar c = 1; var d = 2; // about d\n";
+    let formatted = format(source).unwrap();
+    assert_eq!(
+        "var a = 1; // before\nvar b = (a /* in */ ); // after\na; /* same line */\nb = b; /* x */ // y\n/* own line */\na = -a;\nvar c = 1;\nvar d = 2; // about d\n",
+        formatted
+    );
+    assert_eq!(formatted, format(&formatted).unwrap());
+    // the comments are gone once scanned, so both must do the same
+    assert_eq!(globals(source), globals(&formatted));
+}
+
+#[test]
+fn format_unary_test() {
+    let source = "var x = 1; var a = - --x; var b = - -x; var c = -(-x);";
+    let formatted = format(source).unwrap();
+    assert_eq!("var x = 1;\nvar a = - --x;\nvar b = - -x;\nvar c = -(-x);\n", formatted);
+    assert_eq!(formatted, format(&formatted).unwrap());
+    assert_eq!(globals(source), globals(&formatted));
+}
+
+// the globals after running `source`, to check formatting keeps what a program does
+#[cfg(test)]
+fn globals(source: &str) -> Vec<(String, crate::interpreter::Value)> {
+    let interpreter = crate::interpreter::Interpreter::default();
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    interpreter.execute(&parser.ast, &stmts).unwrap();
+    interpreter.globals()
+}
+
+#[test]
+fn format_width_test() {
+    let source = format!("({}) ? 1 : 2;", ["\"aaaaaaaaaaaaaaaa\""; 5].join(" + "));
+    let formatted = format(&source).unwrap();
+    assert_eq!(
+        "(\n    \"aaaaaaaaaaaaaaaa\" + \"aaaaaaaaaaaaaaaa\" + \"aaaaaaaaaaaaaaaa\"\n        + \"aaaaaaaaaaaaaaaa\"\n        + \"aaaaaaaaaaaaaaaa\"\n)\n    ? 1\n    : 2;\n",
+        formatted
+    );
+    assert_eq!(formatted, format(&formatted).unwrap());
+}
@@ -0,0 +1,192 @@
+// the heap of the VM's objects, freed by a mark and sweep collector: the VM marks its
+// roots, then `Heap::collect` traces from them and frees the rest
+
+use std::mem;
+
+use crate::value::{Unpacked, VmValue};
+
+// an object on the heap, only valid until the collection which frees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(crate) u32);
+
+#[derive(Debug)]
+pub enum Obj {
+    String(String),
+    // only for testing the tracing, until a Lox object keeps others alive
+    #[cfg(test)]
+    List(Vec<VmValue>),
+}
+
+impl Obj {
+    // what it costs, for the collection threshold
+    fn size(&self) -> usize {
+        mem::size_of::<Entry>()
+            + match self {
+                Obj::String(s) => s.capacity(),
+                #[cfg(test)]
+                Obj::List(values) => values.capacity() * mem::size_of::<VmValue>(),
+            }
+    }
+
+    // the objects this one keeps alive
+    #[cfg_attr(not(test), allow(unused_variables, clippy::ptr_arg))]
+    fn trace(&self, gray: &mut Vec<ObjRef>) {
+        match self {
+            Obj::String(_) => {}
+            #[cfg(test)]
+            Obj::List(values) => {
+                for value in values {
+                    if let Unpacked::Obj(obj) = value.unpack() {
+                        gray.push(obj);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Entry {
+    obj: Obj,
+    marked: bool,
+}
+
+// the first collection happens after this many bytes
+const FIRST_GC: usize = 1024 * 1024;
+
+pub struct Heap {
+    // freed slots are `None` and reused
+    objects: Vec<Option<Entry>>,
+    free: Vec<u32>,
+    bytes: usize,
+    next_gc: usize,
+    // the threshold is this many times the bytes alive after a collection
+    pub growth: f64,
+    // collect before every allocation, to find missing roots
+    pub stress: bool,
+    gray: Vec<ObjRef>,
+}
+
+impl Heap {
+    pub fn new(growth: f64, stress: bool) -> Self {
+        Heap {
+            objects: vec![],
+            free: vec![],
+            bytes: 0,
+            next_gc: FIRST_GC,
+            growth,
+            stress,
+            gray: vec![],
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.stress || self.bytes > self.next_gc
+    }
+
+    pub fn alloc(&mut self, obj: Obj) -> ObjRef {
+        self.bytes += obj.size();
+        let entry = Some(Entry { obj, marked: false });
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = entry;
+                ObjRef(index)
+            }
+            None => {
+                self.objects.push(entry);
+                ObjRef(self.objects.len() as u32 - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Obj {
+        match &self.objects[obj.0 as usize] {
+            Some(entry) => &entry.obj,
+            None => panic!("{obj:?} was freed"),
+        }
+    }
+
+    pub fn mark_value(&mut self, value: VmValue) {
+        if let Unpacked::Obj(obj) = value.unpack() {
+            self.mark(obj);
+        }
+    }
+
+    pub fn mark(&mut self, obj: ObjRef) {
+        let entry = self.objects[obj.0 as usize].as_mut().expect("marking a freed object");
+        if !entry.marked {
+            entry.marked = true;
+            self.gray.push(obj);
+        }
+    }
+
+    // free what is not reachable from the marked roots
+    pub fn collect(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            let mut children = vec![];
+            self.get(obj).trace(&mut children);
+            for child in children {
+                self.mark(child);
+            }
+        }
+
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => entry.marked = false,
+                Some(entry) => {
+                    self.bytes -= entry.obj.size();
+                    *slot = None;
+                    self.free.push(index as u32);
+                }
+                None => {}
+            }
+        }
+        self.next_gc = FIRST_GC.max((self.bytes as f64 * self.growth) as usize);
+    }
+
+    // how many objects are alive
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn collect_test() {
+    let mut heap = Heap::new(2.0, false);
+    let a = heap.alloc(Obj::String("a".to_owned()));
+    let b = heap.alloc(Obj::String("b".to_owned()));
+    heap.mark_value(VmValue::obj(b));
+    heap.collect();
+    assert_eq!(1, heap.len());
+    assert!(matches!(heap.get(b), Obj::String(s) if s == "b"));
+
+    // the slot of `a` is reused
+    assert_eq!(a, heap.alloc(Obj::String("c".to_owned())));
+    heap.collect();
+    assert!(heap.is_empty());
+}
+
+#[test]
+fn trace_test() {
+    let mut heap = Heap::new(2.0, false);
+    let a = heap.alloc(Obj::String("a".to_owned()));
+    let inner = heap.alloc(Obj::List(vec![VmValue::obj(a), VmValue::number(1.0)]));
+    let outer = heap.alloc(Obj::List(vec![VmValue::obj(inner), VmValue::obj(a)]));
+    heap.alloc(Obj::String("garbage".to_owned()));
+
+    // only the outer list is a root, what it reaches survives
+    heap.mark(outer);
+    heap.collect();
+    assert_eq!(3, heap.len());
+    assert!(matches!(heap.get(a), Obj::String(s) if s == "a"));
+    assert!(matches!(heap.get(inner), Obj::List(values) if values.len() == 2));
+
+    heap.mark(inner);
+    heap.collect();
+    assert_eq!(2, heap.len());
+    heap.collect();
+    assert!(heap.is_empty());
+}
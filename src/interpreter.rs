@@ -0,0 +1,356 @@
+use std::{cell::RefCell, collections::HashMap, fmt::Display};
+
+use crate::{
+    ast::Ast,
+    expr::{
+        AssignExpr, BinaryExpr, ConditionalExpr, ExprId, ExprVisitor, GroupingExpr, LiteralExpr,
+        PostfixExpr, UnaryExpr, VariableExpr,
+    },
+    stmt::{ExpressionStmt, PrintStmt, StmtId, StmtVisitor, VarStmt},
+    symbol::Symbol,
+    token::{LiteralValue, Token, TokenType},
+};
+
+type Result<T> = std::result::Result<T, RuntimeErr>;
+
+#[derive(Debug)]
+pub struct RuntimeErr {
+    pub token: Token,
+    pub message: String,
+}
+
+impl RuntimeErr {
+    fn new(token: &Token, message: &str) -> Self {
+        Self {
+            token: token.clone(),
+            message: message.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+impl Value {
+    // false and nil are falsey, everything else is truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl From<&LiteralValue> for Value {
+    fn from(value: &LiteralValue) -> Self {
+        match value {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Bool(v) => Value::Bool(*v),
+            LiteralValue::Nubmer(v) => Value::Number(*v),
+            LiteralValue::String(v) => Value::String(v.to_string()),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(v) => write!(f, "{v}"),
+            Value::Number(v) => write!(f, "{v}"),
+            Value::String(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+// keeps the global variables between calls, so the prompt can run line after line
+#[derive(Default)]
+pub struct Interpreter {
+    globals: RefCell<HashMap<Symbol, Value>>,
+}
+
+impl Interpreter {
+    pub fn interpret(&self, ast: &Ast, expr: ExprId) -> Result<Value> {
+        expr.visit(ast, self)
+    }
+
+    pub fn execute(&self, ast: &Ast, stmts: &[StmtId]) -> Result<()> {
+        for stmt in stmts {
+            stmt.visit(ast, self)?;
+        }
+
+        Ok(())
+    }
+
+    // the global variables sorted by name
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self.globals.borrow().iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    fn undefined(name: &Token) -> RuntimeErr {
+        RuntimeErr::new(name, &format!("Undefined variable '{}'.", name.lexeme))
+    }
+}
+
+// the operators on values, shared by the tree-walker and the VM, errors are the messages
+pub fn unary(operator: &TokenType, right: &Value) -> std::result::Result<Value, &'static str> {
+    match operator {
+        TokenType::Bang => Ok(Value::Bool(!right.is_truthy())),
+        TokenType::Minus => Ok(Value::Number(-number_operand(right)?)),
+        TokenType::Tilde => Ok(Value::Number(!integer_operand(number_operand(right)?)? as f64)),
+        _ => unreachable!("unknown unary operator {:?}", operator),
+    }
+}
+
+pub fn binary(operator: &TokenType, left: Value, right: Value) -> std::result::Result<Value, &'static str> {
+    match operator {
+        TokenType::EqualEqual => Ok(Value::Bool(left == right)),
+        TokenType::BangEqual => Ok(Value::Bool(left != right)),
+        TokenType::Plus => match (left, right) {
+            (Value::Number(l), Value::Number(r)) => Ok(Value::Number(l + r)),
+            (Value::String(l), Value::String(r)) => Ok(Value::String(l + &r)),
+            _ => Err("Operands must be two numbers or two strings."),
+        },
+        _ => {
+            let (l, r) = match (left, right) {
+                (Value::Number(l), Value::Number(r)) => (l, r),
+                _ => return Err("Operands must be numbers."),
+            };
+            Ok(match operator {
+                TokenType::Greater => Value::Bool(l > r),
+                TokenType::GreaterEqual => Value::Bool(l >= r),
+                TokenType::Less => Value::Bool(l < r),
+                TokenType::LessEqual => Value::Bool(l <= r),
+                TokenType::Minus => Value::Number(l - r),
+                TokenType::Star => Value::Number(l * r),
+                TokenType::StarStar => Value::Number(l.powf(r)),
+                TokenType::Slash => Value::Number(l / divisor(r)?),
+                TokenType::Percent => Value::Number(l % divisor(r)?),
+                TokenType::SlashSlash => Value::Number((l / divisor(r)?).floor()),
+                TokenType::Ampersand
+                | TokenType::Pipe
+                | TokenType::Caret
+                | TokenType::LessLess
+                | TokenType::GreaterGreater => bitwise(operator, l, r)?,
+                _ => unreachable!("unknown binary operator {:?}", operator),
+            })
+        }
+    }
+}
+
+fn number_operand(operand: &Value) -> std::result::Result<f64, &'static str> {
+    match operand {
+        Value::Number(v) => Ok(*v),
+        _ => Err("Operand must be a number."),
+    }
+}
+
+// bitwise operators work on numbers without a fractional part
+fn integer_operand(operand: f64) -> std::result::Result<i64, &'static str> {
+    if operand.fract() == 0.0 && operand >= i64::MIN as f64 && operand < i64::MAX as f64 {
+        Ok(operand as i64)
+    } else {
+        Err("Operands must be integers.")
+    }
+}
+
+fn bitwise(operator: &TokenType, left: f64, right: f64) -> std::result::Result<Value, &'static str> {
+    let l = integer_operand(left)?;
+    let r = integer_operand(right)?;
+    let v = match operator {
+        TokenType::Ampersand => l & r,
+        TokenType::Pipe => l | r,
+        TokenType::Caret => l ^ r,
+        TokenType::LessLess | TokenType::GreaterGreater => {
+            if !(0..64).contains(&r) {
+                return Err("Shift amount must be between 0 and 63.");
+            }
+            if *operator == TokenType::LessLess {
+                l << r
+            } else {
+                l >> r
+            }
+        }
+        _ => unreachable!("unknown bitwise operator {:?}", operator),
+    };
+
+    Ok(Value::Number(v as f64))
+}
+
+fn divisor(right: f64) -> std::result::Result<f64, &'static str> {
+    if right == 0.0 {
+        Err("Division by zero.")
+    } else {
+        Ok(right)
+    }
+}
+
+impl ExprVisitor<Result<Value>> for Interpreter {
+    fn visit_literal(&self, _: &Ast, _: ExprId, expr: &LiteralExpr) -> Result<Value> {
+        Ok(Value::from(&expr.value))
+    }
+
+    fn visit_unary(&self, ast: &Ast, _: ExprId, expr: &UnaryExpr) -> Result<Value> {
+        let right = expr.right.visit(ast, self)?;
+        unary(&expr.operator.token_type, &right).map_err(|message| RuntimeErr::new(&expr.operator, message))
+    }
+
+    fn visit_binary(&self, ast: &Ast, _: ExprId, expr: &BinaryExpr) -> Result<Value> {
+        let left = expr.left.visit(ast, self)?;
+        let right = expr.right.visit(ast, self)?;
+        // comma discards the left operand
+        if expr.operator.token_type == TokenType::Comma {
+            return Ok(right);
+        }
+        binary(&expr.operator.token_type, left, right).map_err(|message| RuntimeErr::new(&expr.operator, message))
+    }
+
+    fn visit_grouping(&self, ast: &Ast, _: ExprId, expr: &GroupingExpr) -> Result<Value> {
+        expr.expression.visit(ast, self)
+    }
+
+    fn visit_conditional(&self, ast: &Ast, _: ExprId, expr: &ConditionalExpr) -> Result<Value> {
+        if expr.condition.visit(ast, self)?.is_truthy() {
+            expr.then_branch.visit(ast, self)
+        } else {
+            expr.else_branch.visit(ast, self)
+        }
+    }
+
+    fn visit_variable(&self, _: &Ast, _: ExprId, expr: &VariableExpr) -> Result<Value> {
+        match self.globals.borrow().get(&expr.name.lexeme) {
+            Some(value) => Ok(value.clone()),
+            None => Err(Self::undefined(&expr.name)),
+        }
+    }
+
+    fn visit_assign(&self, ast: &Ast, _: ExprId, expr: &AssignExpr) -> Result<Value> {
+        let value = expr.value.visit(ast, self)?;
+        match self.globals.borrow_mut().get_mut(&expr.name.lexeme) {
+            Some(slot) => *slot = value.clone(),
+            None => return Err(Self::undefined(&expr.name)),
+        }
+
+        Ok(value)
+    }
+
+    fn visit_postfix(&self, _: &Ast, _: ExprId, expr: &PostfixExpr) -> Result<Value> {
+        let mut globals = self.globals.borrow_mut();
+        let Some(slot) = globals.get_mut(&expr.name.lexeme) else {
+            return Err(Self::undefined(&expr.name));
+        };
+        let operator = if expr.operator.token_type == TokenType::PlusPlus { TokenType::Plus } else { TokenType::Minus };
+        let old = slot.clone();
+        *slot = binary(&operator, old.clone(), Value::Number(1.0))
+            .map_err(|message| RuntimeErr::new(&expr.operator, message))?;
+        Ok(old)
+    }
+}
+
+impl StmtVisitor<Result<()>> for Interpreter {
+    fn visit_expression(&self, ast: &Ast, _: StmtId, stmt: &ExpressionStmt) -> Result<()> {
+        stmt.expression.visit(ast, self)?;
+        Ok(())
+    }
+
+    fn visit_print(&self, ast: &Ast, _: StmtId, stmt: &PrintStmt) -> Result<()> {
+        println!("{}", stmt.expression.visit(ast, self)?);
+        Ok(())
+    }
+
+    fn visit_var(&self, ast: &Ast, _: StmtId, stmt: &VarStmt) -> Result<()> {
+        let value = match stmt.initializer {
+            Some(initializer) => initializer.visit(ast, self)?,
+            None => Value::Nil,
+        };
+        self.globals.borrow_mut().insert(stmt.name.lexeme, value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn eval(interpreter: &Interpreter, source: &str) -> Result<Value> {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    let expr = parser.parse().unwrap();
+    interpreter.interpret(&parser.ast, expr)
+}
+
+#[test]
+fn arithmetic_test() {
+    let interpreter = Interpreter::default();
+
+    assert_eq!(Value::Number(-8.0), eval(&interpreter, "-2 ** 3").unwrap());
+    assert_eq!(Value::Number(512.0), eval(&interpreter, "2 ** 3 ** 2").unwrap());
+    assert_eq!(Value::Number(1.0), eval(&interpreter, "7 % 3").unwrap());
+    assert_eq!(Value::Number(-4.0), eval(&interpreter, "-7//2").unwrap());
+    assert_eq!(Value::String("ab".to_owned()), eval(&interpreter, "1 > 2 ? \"a\" : \"a\" + \"b\"").unwrap());
+    assert_eq!("Division by zero.", eval(&interpreter, "1//0").unwrap_err().message);
+    assert_eq!("Division by zero.", eval(&interpreter, "1 % (2 - 2)").unwrap_err().message);
+}
+
+#[test]
+fn bitwise_test() {
+    let interpreter = Interpreter::default();
+
+    assert_eq!(Value::Number(7.0), eval(&interpreter, "5 | 3 ^ 1 & 7").unwrap());
+    assert_eq!(Value::Number(-6.0), eval(&interpreter, "~5").unwrap());
+    assert_eq!(Value::Number(-2.0), eval(&interpreter, "1 << 3 >> 2 ^ -4").unwrap());
+    assert_eq!("Operands must be integers.", eval(&interpreter, "1.5 & 1").unwrap_err().message);
+    assert_eq!("Operands must be integers.", eval(&interpreter, "~0.5").unwrap_err().message);
+    assert_eq!("Shift amount must be between 0 and 63.", eval(&interpreter, "1 << 64").unwrap_err().message);
+}
+
+#[test]
+fn globals_test() {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    let interpreter = Interpreter::default();
+    let mut parser = Parser::new(Scanner::new("var a = 1; var b;".to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    interpreter.execute(&parser.ast, &stmts).unwrap();
+
+    assert_eq!(Value::Number(3.0), eval(&interpreter, "b = a = a + 2").unwrap());
+    assert_eq!(Value::Number(6.0), eval(&interpreter, "a + b").unwrap());
+    assert_eq!("Undefined variable 'c'.", eval(&interpreter, "c = 1").unwrap_err().message);
+    assert_eq!(
+        vec![("a".to_owned(), Value::Number(3.0)), ("b".to_owned(), Value::Number(3.0))],
+        interpreter.globals()
+    );
+}
+
+#[test]
+fn compound_assignment_test() {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    let interpreter = Interpreter::default();
+    let mut parser = Parser::new(Scanner::new("var x = 1; var s = \"a\";".to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    interpreter.execute(&parser.ast, &stmts).unwrap();
+
+    assert_eq!(Value::Number(3.0), eval(&interpreter, "x += 2").unwrap());
+    assert_eq!(Value::Number(9.0), eval(&interpreter, "x *= 3").unwrap());
+    assert_eq!(Value::Number(8.0), eval(&interpreter, "x -= 1").unwrap());
+    assert_eq!(Value::Number(2.0), eval(&interpreter, "x /= 4").unwrap());
+    // postfix gives the value before the update
+    assert_eq!(Value::Number(2.0), eval(&interpreter, "x++").unwrap());
+    assert_eq!(Value::Number(4.0), eval(&interpreter, "++x").unwrap());
+    assert_eq!(Value::Number(3.0), eval(&interpreter, "--x").unwrap());
+    assert_eq!(Value::Number(3.0), eval(&interpreter, "x--").unwrap());
+    assert_eq!(Value::Number(4.0), eval(&interpreter, "x += x").unwrap());
+    assert_eq!(Value::String("ab".to_owned()), eval(&interpreter, "s += \"b\"").unwrap());
+    assert_eq!(Value::Number(-4.0), eval(&interpreter, "-x").unwrap());
+    // only a variable is incremented or decremented
+    for source in ["--1", "++1", "--(x)", "++(x)", "1--", "x++++"] {
+        crate::runner::reset_error();
+        Parser::new(Scanner::new(source.to_owned()).scan()).parse();
+        assert!(crate::runner::had_error(), "{source}");
+    }
+    assert_eq!("Undefined variable 'y'.", eval(&interpreter, "y += 1").unwrap_err().message);
+    assert_eq!("Operands must be two numbers or two strings.", eval(&interpreter, "s++").unwrap_err().message);
+}
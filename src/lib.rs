@@ -1,7 +1,19 @@
 pub mod exercise;
+pub mod symbol;
 pub mod token;
 pub mod scanner;
 pub mod runner;
+pub mod ast;
 pub mod expr;
-pub mod gen;
+pub mod stmt;
 pub mod parser;
+pub mod interpreter;
+pub mod formatter;
+pub mod dump;
+pub mod cli;
+pub mod chunk;
+pub mod compiler;
+pub mod value;
+pub mod gc;
+pub mod vm;
+pub mod loxc;
@@ -0,0 +1,388 @@
+// the `.loxc` file of a compiled script, all numbers big-endian:
+//
+//     magic     "LOXC"
+//     version   u16
+//     functions u32, then for each
+//         name      u32 length, UTF-8
+//         constants u32, then for each a tag u8: 0 nil, 1 false, 2 true,
+//                   3 number as f64 bits u64, 4 string as u32 length and UTF-8
+//         code      u32 length, the bytes
+//         lines     u32 runs, then for each the line u32 and how many bytes u32
+//     checksum  u64, FNV-1a of everything before it
+//
+// there are no function declarations yet, so the only function is the script
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    interpreter::Value,
+};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 1;
+
+const SCRIPT: &str = "script";
+
+// the version of a compiled file, `None` for source: the magic alone could start an
+// identifier, but the high byte of the version is 0, which is never in source
+pub fn version(bytes: &[u8]) -> Option<u16> {
+    match bytes.strip_prefix(MAGIC)? {
+        [0, low, ..] => Some(u16::from(*low)),
+        _ => None,
+    }
+}
+
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    version(bytes).is_some()
+}
+
+pub fn write(chunk: &Chunk) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend(VERSION.to_be_bytes());
+    out.extend(1u32.to_be_bytes());
+
+    write_str(&mut out, SCRIPT);
+    out.extend(len(&chunk.constants));
+    for constant in &chunk.constants {
+        match constant {
+            Value::Nil => out.push(0),
+            Value::Bool(false) => out.push(1),
+            Value::Bool(true) => out.push(2),
+            Value::Number(n) => {
+                out.push(3);
+                out.extend(n.to_bits().to_be_bytes());
+            }
+            Value::String(s) => {
+                out.push(4);
+                write_str(&mut out, s);
+            }
+        }
+    }
+    out.extend(len(&chunk.code));
+    out.extend(&chunk.code);
+
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for line in &chunk.lines {
+        match runs.last_mut() {
+            Some((last, count)) if last == line => *count += 1,
+            _ => runs.push((*line, 1)),
+        }
+    }
+    out.extend(len(&runs));
+    for (line, count) in runs {
+        out.extend(line.to_be_bytes());
+        out.extend(count.to_be_bytes());
+    }
+
+    out.extend(checksum(&out).to_be_bytes());
+    out
+}
+
+// the chunk of the script, `Err` says what is wrong with the file: everything is
+// checked before the VM sees it, so a bad file cannot make it panic
+pub fn read(bytes: &[u8]) -> Result<Chunk, String> {
+    if !is_compiled(bytes) {
+        return Err("not a compiled lox file".to_owned());
+    }
+    if bytes.len() < MAGIC.len() + 2 + 8 {
+        return Err("truncated file".to_owned());
+    }
+    let mut reader = Reader {
+        bytes,
+        at: MAGIC.len(),
+    };
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!("unsupported .loxc version {version}, expect {VERSION}"));
+    }
+    let (body, sum) = bytes.split_at(bytes.len() - 8);
+    if checksum(body).to_be_bytes() != sum {
+        return Err("checksum mismatch, the file is truncated or modified".to_owned());
+    }
+    reader.bytes = body;
+
+    let functions = reader.u32()?;
+    if functions != 1 {
+        return Err(format!("expect 1 function, found {functions}"));
+    }
+    let name = reader.str()?;
+    if name != SCRIPT {
+        return Err(format!("expect the function '{SCRIPT}', found '{name}'"));
+    }
+
+    let mut chunk = Chunk::new();
+    for _ in 0..reader.u32()? {
+        let constant = match reader.u8()? {
+            0 => Value::Nil,
+            1 => Value::Bool(false),
+            2 => Value::Bool(true),
+            3 => Value::Number(f64::from_bits(reader.u64()?)),
+            4 => Value::String(reader.str()?),
+            tag => return Err(format!("unknown constant tag {tag}")),
+        };
+        chunk.constants.push(constant);
+    }
+    let code_len = reader.u32()? as usize;
+    chunk.code = reader.take(code_len)?.to_vec();
+    for _ in 0..reader.u32()? {
+        let line = reader.u32()?;
+        let count = reader.u32()? as usize;
+        if chunk.lines.len() + count > chunk.code.len() {
+            return Err("more lines than code".to_owned());
+        }
+        chunk.lines.extend(std::iter::repeat_n(line, count));
+    }
+    if chunk.lines.len() != chunk.code.len() {
+        return Err("fewer lines than code".to_owned());
+    }
+    if reader.at != body.len() {
+        return Err("unexpected bytes after the functions".to_owned());
+    }
+
+    verify(&chunk)?;
+    Ok(chunk)
+}
+
+fn len<T>(items: &[T]) -> [u8; 4] {
+    (items.len() as u32).to_be_bytes()
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    out.extend(len(s.as_bytes()));
+    out.extend(s.as_bytes());
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.at < n {
+            return Err("truncated file".to_owned());
+        }
+        self.at += n;
+        Ok(&self.bytes[self.at - n..self.at])
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "string is not UTF-8".to_owned())
+    }
+}
+
+// the checks the VM relies on: known opcodes with their operands, constants in range,
+// names are strings, jumps land on an instruction, and the stack height is the same
+// on every path and never goes below zero, the code ends at a `Return` or `Jump`
+fn verify(chunk: &Chunk) -> Result<(), String> {
+    let code = &chunk.code;
+    // the stack height coming from jumps, by target offset
+    let mut heights: Vec<Option<usize>> = vec![None; code.len() + 1];
+    let mut starts = vec![false; code.len() + 1];
+    // `None` after an instruction which does not fall through
+    let mut height = Some(0);
+    let mut offset = 0;
+
+    while offset < code.len() {
+        let op =
+            OpCode::try_from(code[offset]).map_err(|byte| format!("unknown opcode {byte} at {offset}"))?;
+        let error = |message: &str| Err(format!("{} at {offset}: {message}", op.name()));
+        starts[offset] = true;
+        height = match (height, heights[offset]) {
+            (Some(a), Some(b)) if a != b => return error("the stack height differs between paths"),
+            (a, b) => a.or(b),
+        };
+
+        let has_operand = matches!(
+            op,
+            OpCode::Constant
+                | OpCode::DefineGlobal
+                | OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::Jump
+                | OpCode::JumpIfFalse
+        );
+        let next = offset + if has_operand { 3 } else { 1 };
+        if next > code.len() {
+            return error("operand past the end of the code");
+        }
+        let operand = if has_operand {
+            chunk.read_u16(offset + 1) as usize
+        } else {
+            0
+        };
+
+        match op {
+            OpCode::Constant if operand >= chunk.constants.len() => return error("constant out of range"),
+            OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                match chunk.constants.get(operand) {
+                    Some(Value::String(_)) => {}
+                    _ => return error("the name is not a string constant"),
+                }
+            }
+            OpCode::Jump | OpCode::JumpIfFalse if next + operand > code.len() => {
+                return error("jump past the end of the code")
+            }
+            _ => {}
+        }
+
+        // what the instruction pops, then pushes
+        let (pops, pushes) = match op {
+            OpCode::Constant | OpCode::Nil | OpCode::True | OpCode::False | OpCode::GetGlobal => (0, 1),
+            OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::JumpIfFalse => (1, 0),
+            OpCode::SetGlobal | OpCode::Negate | OpCode::Not | OpCode::BitNot => (1, 1),
+            OpCode::Jump | OpCode::Return => (0, 0),
+            _ => (2, 1),
+        };
+        if let Some(h) = height {
+            if h < pops {
+                return error("stack underflow");
+            }
+            height = Some(h - pops + pushes);
+        }
+
+        if matches!(op, OpCode::Jump | OpCode::JumpIfFalse) {
+            let target = next + operand;
+            if let Some(h) = height {
+                match heights[target] {
+                    Some(other) if other != h => return error("the stack height differs between paths"),
+                    _ => heights[target] = Some(h),
+                }
+            }
+        }
+        if matches!(op, OpCode::Jump | OpCode::Return) {
+            height = None;
+        }
+        offset = next;
+    }
+
+    if height.is_some() || heights[code.len()].is_some() {
+        return Err("the code runs past its end".to_owned());
+    }
+    match (0..=code.len()).find(|i| heights[*i].is_some() && !starts[*i]) {
+        Some(target) => Err(format!("jump into the middle of an instruction at {target}")),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn round_trip_test() {
+    use crate::{compiler, parser::Parser, scanner::Scanner};
+
+    let source = "var a = 1.5;\nvar b = nil;\n\nprint a > 1 ? \"big\" : true;\nb = -0 == false;";
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    let chunk = compiler::compile(&parser.ast, &stmts).unwrap();
+    let bytes = write(&chunk);
+    assert_eq!(Ok(chunk), read(&bytes));
+
+    for end in 0..bytes.len() {
+        assert!(read(&bytes[..end]).is_err(), "truncated at {end}");
+    }
+    for i in 0..bytes.len() {
+        let mut tampered = bytes.clone();
+        tampered[i] ^= 0x20;
+        assert!(read(&tampered).is_err(), "byte {i} changed");
+    }
+}
+
+#[test]
+fn version_test() {
+    assert_eq!(Some(VERSION), version(&write(&Chunk::new())));
+    assert_eq!(Some(7), version(b"LOXC\0\x07"));
+    // source which happens to start with the magic
+    assert_eq!(None, version(b"LOXCount = 1;"));
+    assert_eq!(None, version(b"LOXC"));
+    assert_eq!(
+        Err("unsupported .loxc version 7, expect 1".to_owned()),
+        read(b"LOXC\0\x07\0\0\0\0\0\0\0\0")
+    );
+}
+
+#[test]
+fn verify_test() {
+    let chunk = |code: &[u8], constants: Vec<Value>| Chunk {
+        code: code.to_vec(),
+        constants,
+        lines: vec![1; code.len()],
+    };
+    let constant = OpCode::Constant as u8;
+    let ret = OpCode::Return as u8;
+    let jump_if_false = OpCode::JumpIfFalse as u8;
+    let pop = OpCode::Pop as u8;
+    let a = || vec![Value::String("a".to_owned())];
+
+    let cases = [
+        (chunk(&[OpCode::Nil as u8, pop, ret], vec![]), "ok"),
+        (chunk(&[pop, ret], vec![]), "OP_POP at 0: stack underflow"),
+        (
+            chunk(&[constant, 0, 1, ret], a()),
+            "OP_CONSTANT at 0: constant out of range",
+        ),
+        (
+            chunk(&[constant, 0], a()),
+            "OP_CONSTANT at 0: operand past the end of the code",
+        ),
+        (
+            chunk(
+                &[OpCode::Nil as u8, OpCode::GetGlobal as u8, 0, 0, ret],
+                vec![Value::Nil],
+            ),
+            "OP_GET_GLOBAL at 1: the name is not a string constant",
+        ),
+        (chunk(&[OpCode::Nil as u8], vec![]), "the code runs past its end"),
+        (chunk(&[99], vec![]), "unknown opcode 99 at 0"),
+        (
+            chunk(&[OpCode::True as u8, jump_if_false, 0, 9, ret], vec![]),
+            "OP_JUMP_IF_FALSE at 1: jump past the end of the code",
+        ),
+        (
+            chunk(
+                &[OpCode::True as u8, jump_if_false, 0, 1, constant, 0, 0, ret],
+                a(),
+            ),
+            "jump into the middle of an instruction at 5",
+        ),
+        (
+            chunk(
+                &[
+                    OpCode::True as u8,
+                    OpCode::True as u8,
+                    jump_if_false,
+                    0,
+                    1,
+                    pop,
+                    ret,
+                ],
+                vec![],
+            ),
+            "OP_RETURN at 6: the stack height differs between paths",
+        ),
+    ];
+    for (chunk, expect) in cases {
+        let bytes = write(&chunk);
+        assert_eq!(expect, read(&bytes).map_or_else(|err| err, |_| "ok".to_owned()));
+    }
+}
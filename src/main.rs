@@ -1,12 +1,40 @@
-use lox::runner;
-use std::env;
+use lox::{
+    cli::{self, Command},
+    runner,
+};
+use std::{env, process};
 
 pub fn main() {
-    let mut args = env::args();
-    println!("{:?}", args);
-    match args.len() {
-        1 => runner::run_prompt(),
-        2 => runner::run_file(&args.nth(1).unwrap()),
-        _ => println!("Oops, something wrong."),
-    }
+    let args: Vec<String> = env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {message}\n\n{}", cli::USAGE);
+            process::exit(runner::EX_USAGE);
+        }
+    };
+
+    let code = match command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            0
+        }
+        Command::Version => {
+            println!("lox {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Command::Repl => {
+            runner::run_prompt();
+            0
+        }
+        Command::Run { paths, backend } => runner::run_files(&paths, backend),
+        Command::Eval { code, backend } => runner::run_source(&code, backend),
+        Command::Check(path) => runner::run_check(&path),
+        Command::Disasm(path) => runner::run_disasm(&path),
+        Command::Compile { path, output } => runner::run_compile(&path, &output),
+        Command::Fmt { paths, check } => !runner::run_fmt(&paths, check) as i32,
+        Command::Ast { path, format } => runner::run_ast(&path, &format),
+        Command::Tokens { path, json } => runner::run_tokens(&path, json),
+    };
+    process::exit(code);
 }
@@ -1,40 +1,197 @@
+use std::ops::Range;
+
 use crate::{
-    expr::{BinaryExpr, Expr, GroupingExpr, LiteralExpr, UnaryExpr},
+    ast::Ast,
+    expr::{
+        AssignExpr, BinaryExpr, ConditionalExpr, Expr, ExprId, GroupingExpr, LiteralExpr, PostfixExpr,
+        UnaryExpr, VariableExpr,
+    },
     runner,
-    token::{Token, TokenType},
+    stmt::{ExpressionStmt, PrintStmt, Stmt, StmtId, VarStmt},
+    token::{LiteralValue, Span, Token, TokenType},
 };
 
 type Result<T> = std::result::Result<T, ParseErr>;
 
 pub enum ParseErr {
-    TokenErr { token: Token, message: &'static str },
+    TokenErr { token: Token, message: String },
     FooErr,
 }
 
 pub struct Parser {
     pub tokens: Vec<Token>,
+    // every parsed node is allocated here
+    pub ast: Ast,
+    // range of tokens each node was parsed from, indexed by `ExprId::index`
+    pub spans: Vec<Range<usize>>,
+    // the same for statements, indexed by `StmtId::index`
+    pub stmt_spans: Vec<Range<usize>>,
+    // the prompt lets a last expression statement leave out its ';'
+    pub repl: bool,
     current: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            ast: Ast::new(),
+            spans: vec![],
+            stmt_spans: vec![],
+            repl: false,
+            current: 0,
+        }
     }
 
-    pub fn parse(&mut self) -> Option<Expr> {
+    // a single expression
+    pub fn parse(&mut self) -> Option<ExprId> {
         self.expression().ok()
     }
 
-    pub fn expression(&mut self) -> Result<Expr> {
-        self.equality()
+    // a whole program, `None` if a statement had an error, which is reported already
+    pub fn parse_program(&mut self) -> Option<Vec<StmtId>> {
+        let mut stmts = vec![];
+        let mut ok = true;
+        while !self.is_at_end() && self.peek().token_type != TokenType::Eof {
+            match self.declaration() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(_) => {
+                    ok = false;
+                    self.syncronize();
+                }
+            }
+        }
+
+        ok.then_some(stmts)
+    }
+
+    fn declaration(&mut self) -> Result<StmtId> {
+        let start = self.current;
+        let stmt = if self.is_match(&[TokenType::Var]) {
+            let name = self.consume(TokenType::Identifier, "Expect variable name.")?.clone();
+            let initializer = if self.is_match(&[TokenType::Equal]) {
+                Some(self.expression()?)
+            } else {
+                None
+            };
+            self.consume(TokenType::SemiColon, "Expect ';' after variable declaration.")?;
+            Stmt::Var(VarStmt { name, initializer })
+        } else {
+            self.statement()?
+        };
+
+        Ok(self.add_stmt(start, stmt))
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        if self.is_match(&[TokenType::Print]) {
+            let expression = self.expression()?;
+            self.consume(TokenType::SemiColon, "Expect ';' after value.")?;
+            return Ok(Stmt::Print(PrintStmt { expression }));
+        }
+
+        let expression = self.expression()?;
+        if !(self.repl && self.check(&TokenType::Eof)) {
+            self.consume(TokenType::SemiColon, "Expect ';' after expression.")?;
+        }
+        Ok(Stmt::Expression(ExpressionStmt { expression }))
+    }
+
+    // source range a parsed node covers
+    pub fn span(&self, expr: ExprId) -> Span {
+        self.token_span(&self.spans[expr.index()])
+    }
+
+    pub fn stmt_span(&self, stmt: StmtId) -> Span {
+        self.token_span(&self.stmt_spans[stmt.index()])
+    }
+
+    fn token_span(&self, range: &Range<usize>) -> Span {
+        let first = &self.tokens[range.start];
+        let (end_line, end_column) = self.tokens[range.end.max(range.start + 1) - 1].end();
+        Span {
+            start_line: first.line,
+            start_column: first.column,
+            end_line,
+            end_column,
+        }
+    }
+
+    pub fn expression(&mut self) -> Result<ExprId> {
+        self.comma()
+    }
+
+    // comma has the lowest precedence, evaluates left then yields right
+    pub fn comma(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.assignment()?;
+        while self.is_match(&[TokenType::Comma]) {
+            let operator = self.previous().clone();
+            let right = self.assignment()?;
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    // right-associative, only variables can be assigned to; `x += v` is `x = x + v`
+    pub fn assignment(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let expr = self.conditional()?;
+        if self.is_match(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = self.assignment()?;
+            if let Expr::Variable(VariableExpr { name }) = &self.ast[expr] {
+                let name = name.clone();
+                return Ok(self.add_expr(start, Expr::Assign(AssignExpr { name, value })));
+            }
+            self.error(&equals, "Invalid assignment target.");
+        } else if self.is_match(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+        ]) {
+            let operator = self.previous().clone();
+            let value = self.assignment()?;
+            if self.is_variable(expr) {
+                return Ok(self.update(start, expr, &operator, value));
+            }
+            self.error(&operator, "Invalid assignment target.");
+        }
+
+        Ok(expr)
+    }
+
+    // right-associative: a ? b : c ? d : e is a ? b : (c ? d : e)
+    pub fn conditional(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let condition = self.equality()?;
+        if self.is_match(&[TokenType::Question]) {
+            let then_branch = self.expression()?;
+            self.consume(TokenType::Colon, "Expect ':' after then branch of conditional expression.")?;
+            let else_branch = self.conditional()?;
+            Ok(self.add_expr(start, Expr::Conditional(ConditionalExpr {
+                condition,
+                then_branch,
+                else_branch,
+            })))
+        } else {
+            Ok(condition)
+        }
     }
 
-    pub fn equality(&mut self) -> Result<Expr> {
+    pub fn equality(&mut self) -> Result<ExprId> {
+        let start = self.current;
         let mut expr = self.comparison()?;
         while self.is_match(&[TokenType::EqualEqual, TokenType::BangEqual]) {
             let operator = self.previous().clone();
             let right = self.comparison()?;
-            expr = Expr::Binary(Box::new(BinaryExpr {
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
                 left: expr,
                 right,
                 operator,
@@ -44,17 +201,82 @@ impl Parser {
         Ok(expr)
     }
 
-    pub fn comparison(&mut self) -> Result<Expr> {
-        let mut expr = self.term()?;
+    pub fn comparison(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.bit_or()?;
         while self.is_match(&[
             TokenType::Less,
             TokenType::LessEqual,
             TokenType::Greater,
             TokenType::GreaterEqual,
         ]) {
+            let operator = self.previous().clone();
+            let right = self.bit_or()?;
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn bit_or(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.bit_xor()?;
+        while self.is_match(&[TokenType::Pipe]) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn bit_xor(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.bit_and()?;
+        while self.is_match(&[TokenType::Caret]) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn bit_and(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.shift()?;
+        while self.is_match(&[TokenType::Ampersand]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            }));
+        }
+
+        Ok(expr)
+    }
+
+    pub fn shift(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.term()?;
+        while self.is_match(&[TokenType::LessLess, TokenType::GreaterGreater]) {
             let operator = self.previous().clone();
             let right = self.term()?;
-            expr = Expr::Binary(Box::new(BinaryExpr {
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
                 left: expr,
                 right,
                 operator,
@@ -64,12 +286,13 @@ impl Parser {
         Ok(expr)
     }
 
-    pub fn term(&mut self) -> Result<Expr> {
+    pub fn term(&mut self) -> Result<ExprId> {
+        let start = self.current;
         let mut expr = self.factor()?;
         while self.is_match(&[TokenType::Minus, TokenType::Plus]) {
             let operator = self.previous().clone();
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(BinaryExpr {
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
                 left: expr,
                 right,
                 operator,
@@ -79,12 +302,18 @@ impl Parser {
         Ok(expr)
     }
 
-    pub fn factor(&mut self) -> Result<Expr> {
+    pub fn factor(&mut self) -> Result<ExprId> {
+        let start = self.current;
         let mut expr = self.unary()?;
-        while self.is_match(&[TokenType::Star, TokenType::Slash]) {
+        while self.is_match(&[
+            TokenType::Star,
+            TokenType::Slash,
+            TokenType::Percent,
+            TokenType::SlashSlash,
+        ]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            expr = Expr::Binary(Box::new(BinaryExpr {
+            expr = self.add_expr(start, Expr::Binary(BinaryExpr {
                 left: expr,
                 right,
                 operator,
@@ -94,17 +323,95 @@ impl Parser {
         Ok(expr)
     }
 
-    pub fn unary(&mut self) -> Result<Expr> {
-        if self.is_match(&[TokenType::Bang, TokenType::Minus]) {
+    pub fn unary(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        if self.is_match(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            Ok(self.add_expr(start, Expr::Unary(UnaryExpr { operator, right })))
+        } else if self.is_match(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
             let operator = self.previous().clone();
             let right = self.unary()?;
-            Ok(Expr::Unary(Box::new(UnaryExpr { operator, right })))
+            if self.is_variable(right) {
+                return Ok(self.increment(start, right, &operator));
+            }
+            self.error(&operator, "Invalid increment or decrement target.");
+            Ok(right)
         } else {
-            self.primary()
+            self.power()
         }
     }
 
-    pub fn primary(&mut self) -> Result<Expr> {
+    // right-associative and tighter than a unary on its left: -2 ** -2 is -(2 ** (-2))
+    pub fn power(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let expr = self.postfix()?;
+        if self.is_match(&[TokenType::StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            Ok(self.add_expr(start, Expr::Binary(BinaryExpr {
+                left: expr,
+                right,
+                operator,
+            })))
+        } else {
+            Ok(expr)
+        }
+    }
+
+    pub fn postfix(&mut self) -> Result<ExprId> {
+        let start = self.current;
+        let mut expr = self.primary()?;
+        while self.is_match(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let operator = self.previous().clone();
+            if let Expr::Variable(VariableExpr { name }) = &self.ast[expr] {
+                let name = name.clone();
+                expr = self.add_expr(start, Expr::Postfix(PostfixExpr { name, operator }));
+            } else {
+                self.error(&operator, "Invalid increment or decrement target.");
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn is_variable(&self, expr: ExprId) -> bool {
+        matches!(self.ast[expr], Expr::Variable(_))
+    }
+
+    // `++x` is `x = x + 1` and evaluates to the new value, as `x += 1` does
+    fn increment(&mut self, start: usize, target: ExprId, operator: &Token) -> ExprId {
+        let one = self.add_expr(start, Expr::Literal(LiteralExpr {
+            value: LiteralValue::Nubmer(1.0),
+        }));
+        self.update(start, target, operator, one)
+    }
+
+    // `x op= value` as `x = x op value`, the variable `target` is read once; the operator
+    // keeps the position of the compound one, the formatter finds how it was written
+    fn update(&mut self, start: usize, target: ExprId, compound: &Token, value: ExprId) -> ExprId {
+        let Expr::Variable(VariableExpr { name }) = &self.ast[target] else {
+            unreachable!("only variables are updated");
+        };
+        let name = name.clone();
+        let (token_type, lexeme) = match compound.token_type {
+            TokenType::PlusEqual | TokenType::PlusPlus => (TokenType::Plus, "+"),
+            TokenType::MinusEqual | TokenType::MinusMinus => (TokenType::Minus, "-"),
+            TokenType::StarEqual => (TokenType::Star, "*"),
+            TokenType::SlashEqual => (TokenType::Slash, "/"),
+            _ => unreachable!("not a compound operator {:?}", compound.token_type),
+        };
+        let operator = Token::new(token_type, lexeme, None, compound.line, compound.column);
+        let binary = self.add_expr(start, Expr::Binary(BinaryExpr {
+            left: target,
+            right: value,
+            operator,
+        }));
+        self.add_expr(start, Expr::Assign(AssignExpr { name, value: binary }))
+    }
+
+    pub fn primary(&mut self) -> Result<ExprId> {
+        let start = self.current;
         if self.is_match(&[
             TokenType::Number,
             TokenType::String,
@@ -112,26 +419,95 @@ impl Parser {
             TokenType::True,
             TokenType::False,
         ]) {
-            return Ok(Expr::Literal(Box::new(LiteralExpr {
+            return Ok(self.add_expr(start, Expr::Literal(LiteralExpr {
                 value: self.previous().literal.clone().unwrap(),
             })));
         }
 
+        if self.is_match(&[TokenType::Identifier]) {
+            return Ok(self.add_expr(start, Expr::Variable(VariableExpr {
+                name: self.previous().clone(),
+            })));
+        }
+
         if self.is_match(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
             // first place exception will happen
             self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
-            Ok(Expr::Grouping(Box::new(GroupingExpr { expression: expr })))
+            Ok(self.add_expr(start, Expr::Grouping(GroupingExpr { expression: expr })))
+        } else if self.missing_left_operand()? {
+            // the error is reported already, the placeholder is never evaluated
+            Ok(self.add_expr(start, Expr::Literal(LiteralExpr {
+                value: LiteralValue::Nil,
+            })))
         } else {
             Err(self.error(self.peek(), "Expect expression."))
         }
     }
 
-    fn error(&self, token: &Token, message: &'static str) -> ParseErr {
+    // error production: a binary operator at the start of an expression, report it,
+    // then parse and discard the right operand at the operator's precedence
+    fn missing_left_operand(&mut self) -> Result<bool> {
+        let right_operand: fn(&mut Self) -> Result<ExprId> = if self.is_match(&[TokenType::Comma]) {
+            Self::assignment
+        } else if self.is_match(&[TokenType::EqualEqual, TokenType::BangEqual]) {
+            Self::comparison
+        } else if self.is_match(&[
+            TokenType::Less,
+            TokenType::LessEqual,
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+        ]) {
+            Self::bit_or
+        } else if self.is_match(&[TokenType::Pipe]) {
+            Self::bit_xor
+        } else if self.is_match(&[TokenType::Caret]) {
+            Self::bit_and
+        } else if self.is_match(&[TokenType::Ampersand]) {
+            Self::shift
+        } else if self.is_match(&[TokenType::LessLess, TokenType::GreaterGreater]) {
+            Self::term
+        } else if self.is_match(&[TokenType::Plus]) {
+            Self::factor
+        } else if self.is_match(&[
+            TokenType::Star,
+            TokenType::Slash,
+            TokenType::Percent,
+            TokenType::SlashSlash,
+            TokenType::StarStar,
+        ]) {
+            Self::unary
+        } else {
+            return Ok(false);
+        };
+
+        let operator = self.previous().clone();
+        self.error(
+            &operator,
+            &format!("Binary operator '{}' needs a left operand", operator.lexeme),
+        );
+        right_operand(self)?;
+
+        Ok(true)
+    }
+
+    fn add_expr(&mut self, start: usize, expr: Expr) -> ExprId {
+        let id = self.ast.add_expr(expr);
+        self.spans.push(start..self.current);
+        id
+    }
+
+    fn add_stmt(&mut self, start: usize, stmt: Stmt) -> StmtId {
+        let id = self.ast.add_stmt(stmt);
+        self.stmt_spans.push(start..self.current);
+        id
+    }
+
+    fn error(&self, token: &Token, message: &str) -> ParseErr {
         runner::error_token(token, message);
         ParseErr::TokenErr {
             token: token.clone(),
-            message,
+            message: message.to_owned(),
         }
     }
 
@@ -158,7 +534,6 @@ impl Parser {
         }
     }
 
-    #[allow(unused)]
     fn syncronize(&mut self) {
         use TokenType::*;
         self.advance();
@@ -194,3 +569,54 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
 }
+
+#[test]
+fn conditional_test() {
+    use crate::{exercise::ch5::AstVisitor, scanner::Scanner};
+
+    let tokens = Scanner::new("1 == 2 ? 3 : 4 ? 5 : 6".to_owned()).scan();
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse().unwrap();
+    assert_eq!("(?: (== 1 2) 3 (?: 4 5 6))", expr.visit(&parser.ast, &AstVisitor));
+}
+
+#[test]
+fn comma_test() {
+    use crate::{exercise::ch5::AstVisitor, scanner::Scanner};
+
+    let tokens = Scanner::new("1, 2 ? 3, 4 : 5, 6".to_owned()).scan();
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse().unwrap();
+    assert_eq!("(, (, 1 (?: 2 (, 3 4) 5)) 6)", expr.visit(&parser.ast, &AstVisitor));
+}
+
+#[test]
+fn missing_left_operand_test() {
+    use crate::{exercise::ch5::AstVisitor, scanner::Scanner};
+
+    // parsing goes on after the error, the right operand `3 * 4` is consumed
+    let tokens = Scanner::new("(+ 3 * 4) == 5".to_owned()).scan();
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse().unwrap();
+    assert_eq!("(== (Group nil) 5)", expr.visit(&parser.ast, &AstVisitor));
+}
+
+#[test]
+fn power_test() {
+    use crate::{exercise::ch5::AstVisitor, scanner::Scanner};
+
+    let tokens = Scanner::new("-2 ** 3 ** -1 % 4//5".to_owned()).scan();
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse().unwrap();
+    assert_eq!("(// (% (- (** 2 (** 3 (- 1)))) 4) 5)", expr.visit(&parser.ast, &AstVisitor));
+}
+
+#[test]
+fn bitwise_test() {
+    use crate::{exercise::ch5::AstVisitor, scanner::Scanner};
+
+    let tokens = Scanner::new("1 | 2 ^ 3 & 4 << 5 + 6 < ~7".to_owned()).scan();
+    let mut parser = Parser::new(tokens);
+    let expr = parser.parse().unwrap();
+    assert_eq!("(< (| 1 (^ 2 (& 3 (<< 4 (+ 5 6))))) (~ 7))", expr.visit(&parser.ast, &AstVisitor));
+}
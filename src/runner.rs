@@ -1,15 +1,54 @@
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
 use std::{
-    fs::File,
-    io::{stdin, stdout, Write},
+    cell::Cell,
+    env,
+    fs::{self, File},
+    io::{self, stdin, Read},
+    path::{Path, PathBuf},
+    time::Instant,
 };
 
 use crate::{
-    exercise::ch5::AstVisitor,
+    chunk::Chunk,
+    cli::Backend,
+    compiler, dump, formatter,
+    loxc,
+    interpreter::{Interpreter, RuntimeErr, Value},
     parser::Parser,
-    scanner::Scanner,
+    scanner::{Scanner, KEYWORDS},
+    stmt::Stmt,
     token::{Token, TokenType},
+    vm::{Vm, VmOptions},
 };
-static mut HAS_ERR: bool = false;
+
+// exit codes, as in sysexits.h
+pub const EX_USAGE: i32 = 64;
+pub const EX_DATAERR: i32 = 65;
+pub const EX_NOINPUT: i32 = 66;
+pub const EX_SOFTWARE: i32 = 70;
+pub const EX_CANTCREAT: i32 = 73;
+
+// per thread, so tests running in parallel don't see each other's errors
+thread_local! {
+    static HAS_ERR: Cell<bool> = const { Cell::new(false) };
+    static HAS_RUNTIME_ERR: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn had_error() -> bool {
+    HAS_ERR.get()
+}
+
+pub fn had_runtime_error() -> bool {
+    HAS_RUNTIME_ERR.get()
+}
+
+pub fn reset_error() {
+    HAS_ERR.set(false);
+    HAS_RUNTIME_ERR.set(false);
+}
 
 pub fn error(line: u32, message: &str) {
     report(line, "", message);
@@ -23,38 +62,569 @@ pub fn error_token(token: &Token, message: &str) {
     }
 }
 
+pub fn runtime_error(err: &RuntimeErr) {
+    runtime_error_at(err.token.line, &err.message);
+}
+
+pub fn runtime_error_at(line: u32, message: &str) {
+    HAS_RUNTIME_ERR.set(true);
+    eprintln!("[line{line}] Runtime error: {message}");
+}
+
 fn report(line: u32, position: &str, message: &str) {
-    unsafe {
-        HAS_ERR = true;
+    HAS_ERR.set(true);
+    eprintln!("[line{line}] Error{position}: {message}");
+}
+
+// run the files in order, a directory runs the `.lox` files in it sorted by path,
+// the exit code is the one of the first file which failed
+pub fn run_files(paths: &[String], backend: Backend) -> i32 {
+    let mut code = 0;
+    for path in paths {
+        let files = if Path::new(path).is_dir() {
+            match lox_files(Path::new(path)) {
+                Ok(files) => files,
+                Err(err) => {
+                    eprintln!("cannot read directory '{path}': {}", io_message(&err));
+                    code = if code == 0 { EX_NOINPUT } else { code };
+                    continue;
+                }
+            }
+        } else {
+            vec![PathBuf::from(path)]
+        };
+
+        for file in files {
+            let file_code = run_file(&file.to_string_lossy(), backend);
+            code = if code == 0 { file_code } else { code };
+        }
     }
-    println!("[line{line}] Error{position}: {message}");
+
+    code
+}
+
+fn lox_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut files = vec![];
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(lox_files(&entry)?);
+        } else if entry.extension().is_some_and(|ext| ext == "lox") {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+// `-` reads standard input
+// a compiled file always runs on the VM
+pub fn run_file(path: &str, backend: Backend) -> i32 {
+    let bytes = match read_bytes(path) {
+        Ok(bytes) => bytes,
+        Err(code) => return code,
+    };
+    if !loxc::is_compiled(&bytes) {
+        return match decode(path, bytes) {
+            Ok(source) => run_source(&source, backend),
+            Err(message) => {
+                eprintln!("{message}");
+                EX_DATAERR
+            }
+        };
+    }
+
+    reset_error();
+    match read_compiled(path, &bytes) {
+        Ok(chunk) => run_chunk(&chunk, vm_options(backend)),
+        Err(code) => return code,
+    }
+    exit_code()
+}
+
+// run code given with `-e`
+pub fn run_source(source: &str, backend: Backend) -> i32 {
+    reset_error();
+    match backend {
+        Backend::Tree => run(source),
+        Backend::Vm(options) => run_vm(source, options),
+    }
+    exit_code()
+}
+
+// scan and parse only, there is no resolver yet
+pub fn run_check(path: &str) -> i32 {
+    let source = match read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    reset_error();
+    Parser::new(Scanner::new(source).scan()).parse_program();
+    exit_code()
 }
 
-pub fn run_file(path: &str) {
-    let cnt = std::io::read_to_string(File::open(path).unwrap()).unwrap();
-    run(&cnt);
+fn exit_code() -> i32 {
+    if had_error() {
+        EX_DATAERR
+    } else if had_runtime_error() {
+        EX_SOFTWARE
+    } else {
+        0
+    }
 }
 
-#[allow(unused_must_use)]
+// an interactive prompt with line editing and history, input spanning several lines
+// is read until it is complete, Ctrl-C drops it and Ctrl-D leaves
 pub fn run_prompt() {
-    let mut cnt = String::new();
+    let mut editor = match Editor::<LoxHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("cannot start the prompt: {err}");
+            return;
+        }
+    };
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".lox_history"));
+    if let Some(history) = &history {
+        // there is no history on the first run
+        let _ = editor.load_history(history);
+    }
+
+    editor.set_helper(Some(LoxHelper::default()));
+
+    let mut interpreter = Interpreter::default();
+    let mut source = String::new();
     loop {
-        cnt.clear();
-        print!("> ");
-        stdout().flush();
-        if stdin().read_line(&mut cnt).is_err() {
-            break;
+        if let Some(helper) = editor.helper_mut() {
+            helper.globals = interpreter.globals().into_iter().map(|(name, _)| name).collect();
+        }
+        let prompt = if source.is_empty() { "> " } else { ". " };
+        match editor.readline(prompt) {
+            Ok(line) if source.is_empty() && line.trim_start().starts_with(':') => {
+                let _ = editor.add_history_entry(line.trim());
+                reset_error();
+                run_command(&mut interpreter, line.trim());
+            }
+            Ok(line) => {
+                source.push_str(&line);
+                source.push('\n');
+                if source.trim().is_empty() {
+                    source.clear();
+                    continue;
+                }
+                if Scanner::is_incomplete(&source) {
+                    continue;
+                }
+                let _ = editor.add_history_entry(source.trim_end());
+                reset_error();
+                run_line(&interpreter, &source);
+                source.clear();
+            }
+            Err(ReadlineError::Interrupted) => source.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("cannot read the prompt: {err}");
+                break;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(err) = editor.save_history(history) {
+            eprintln!("cannot save history to '{}': {err}", history.display());
+        }
+    }
+}
+
+// completes keywords and the globals of the session, there are no native functions
+// or classes yet to offer too
+#[derive(Default)]
+struct LoxHelper {
+    globals: Vec<String>,
+}
+
+impl Completer for LoxHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_word(line, pos, &self.globals))
+    }
+}
+
+impl Hinter for LoxHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LoxHelper {}
+
+impl Validator for LoxHelper {}
+
+impl Helper for LoxHelper {}
+
+// the start of the word before `pos` and the names it could be completed to
+fn complete_word(line: &str, pos: usize, globals: &[String]) -> (usize, Vec<String>) {
+    let start = line[..pos]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_ascii_alphanumeric() || *c == '_'))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &line[start..pos];
+    // nothing to complete in numbers, and properties after `.` need classes
+    if word.is_empty() || word.starts_with(|c: char| c.is_ascii_digit()) || line[..start].ends_with('.') {
+        return (pos, vec![]);
+    }
+
+    let mut names: Vec<String> = KEYWORDS
+        .keys()
+        .map(|keyword| keyword.to_string())
+        .chain(globals.iter().cloned())
+        .filter(|name| name.starts_with(word))
+        .collect();
+    names.sort();
+    names.dedup();
+    (start, names)
+}
+
+const COMMANDS: &str = "\
+:tokens <code>  print the tokens of the code
+:ast <code>     print the syntax tree of the code
+:env            list the global variables
+:load <file>    run a file in this session
+:reset          forget all variables
+:time <code>    run the code and print how long it took
+:help           print this help";
+
+// a `:command` of the prompt
+fn run_command(interpreter: &mut Interpreter, line: &str) {
+    let (command, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let arg = arg.trim();
+    match command {
+        ":tokens" => print!("{}", dump::tokens_to_text(&Scanner::new(arg.to_owned()).scan())),
+        ":ast" => {
+            let mut parser = Parser::new(Scanner::new(arg.to_owned()).scan());
+            parser.repl = true;
+            if let Some(stmts) = parser.parse_program().filter(|_| !had_error()) {
+                print!("{}", dump::program_to_sexpr(&parser.ast, &stmts));
+            }
         }
-        run(&cnt);
+        ":env" => {
+            for (name, value) in interpreter.globals() {
+                match value {
+                    Value::String(v) => println!("{name} = \"{v}\""),
+                    _ => println!("{name} = {value}"),
+                }
+            }
+        }
+        ":load" if !arg.is_empty() => {
+            if let Ok(source) = read_source(arg) {
+                run_with(interpreter, &source);
+            }
+        }
+        ":load" => println!("Usage: :load <file>"),
+        ":reset" => *interpreter = Interpreter::default(),
+        ":time" => {
+            let start = Instant::now();
+            run_line(interpreter, arg);
+            println!("took {:?}", start.elapsed());
+        }
+        ":help" => println!("{COMMANDS}"),
+        _ => println!("Unknown command '{command}', try :help."),
     }
 }
 
 fn run(source: &str) {
+    run_with(&Interpreter::default(), source);
+}
+
+fn run_with(interpreter: &Interpreter, source: &str) {
     let scanner = Scanner::new(source.to_owned());
     let tokens = scanner.scan();
     let mut parser = Parser::new(tokens);
-    let expr = parser.parse();
-    if !unsafe { HAS_ERR } && expr.is_some() {
-        println!("{}", expr.unwrap().visit(&AstVisitor));
+    if let Some(stmts) = parser.parse_program() {
+        if !had_error() {
+            if let Err(err) = interpreter.execute(&parser.ast, &stmts) {
+                runtime_error(&err);
+            }
+        }
+    }
+}
+
+fn run_vm(source: &str, options: VmOptions) {
+    if let Some(chunk) = compile(source) {
+        run_chunk(&chunk, options);
+    }
+}
+
+fn run_chunk(chunk: &Chunk, options: VmOptions) {
+    let mut vm = Vm::new(options);
+    if let Err(err) = vm.run(chunk) {
+        runtime_error_at(err.line, &err.message);
+    }
+}
+
+// a compiled file runs on the VM whatever the backend
+fn vm_options(backend: Backend) -> VmOptions {
+    match backend {
+        Backend::Tree => VmOptions::default(),
+        Backend::Vm(options) => options,
+    }
+}
+
+// `None` after reporting the errors
+fn compile(source: &str) -> Option<Chunk> {
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    let stmts = parser.parse_program().filter(|_| !had_error())?;
+    compiler::compile(&parser.ast, &stmts)
+}
+
+// an entry of the prompt: the variables stay in `interpreter` for the next one, and the
+// value of an expression statement is printed, its ';' is optional at the end
+fn run_line(interpreter: &Interpreter, source: &str) {
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    parser.repl = true;
+    let Some(stmts) = parser.parse_program().filter(|_| !had_error()) else {
+        return;
+    };
+
+    for stmt in stmts {
+        let result = match &parser.ast[stmt] {
+            Stmt::Expression(stmt) => interpreter
+                .interpret(&parser.ast, stmt.expression)
+                .map(|value| println!("{value}")),
+            _ => interpreter.execute(&parser.ast, &[stmt]),
+        };
+        if let Err(err) = result {
+            runtime_error(&err);
+            return;
+        }
+    }
+}
+
+// reformat the files in place, or with `check` only report the ones that would change
+pub fn run_fmt(paths: &[String], check: bool) -> bool {
+    let mut ok = true;
+    for path in paths {
+        let Ok(source) = read_source(path) else {
+            ok = false;
+            continue;
+        };
+        let Some(formatted) = formatter::format(&source) else {
+            ok = false;
+            continue;
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("would reformat {path}");
+            ok = false;
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("cannot write '{path}': {}", io_message(&err));
+            ok = false;
+        }
+    }
+
+    ok
+}
+
+// print the tokens of a file, even when it has errors, to see where scanning went wrong
+pub fn run_tokens(path: &str, json: bool) -> i32 {
+    let source = match read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    reset_error();
+    let tokens = Scanner::new(source).scan();
+    if json {
+        println!("{}", dump::tokens_to_json(&tokens));
+    } else {
+        print!("{}", dump::tokens_to_text(&tokens));
+    }
+    exit_code()
+}
+
+// print the bytecode of a source or compiled file
+pub fn run_disasm(path: &str) -> i32 {
+    match load_chunk(path) {
+        Ok(chunk) => {
+            print!("{}", chunk.disassemble(path));
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+// write the bytecode of a file to `output`
+pub fn run_compile(path: &str, output: &str) -> i32 {
+    let chunk = match load_chunk(path) {
+        Ok(chunk) => chunk,
+        Err(code) => return code,
+    };
+    match fs::write(output, loxc::write(&chunk)) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("cannot write '{output}': {}", io_message(&err));
+            EX_CANTCREAT
+        }
+    }
+}
+
+// the chunk of a compiled file, or of a source file once compiled
+fn load_chunk(path: &str) -> Result<Chunk, i32> {
+    let bytes = read_bytes(path)?;
+    reset_error();
+    if loxc::is_compiled(&bytes) {
+        return read_compiled(path, &bytes);
+    }
+    let source = decode(path, bytes).map_err(|message| {
+        eprintln!("{message}");
+        EX_DATAERR
+    })?;
+    compile(&source).ok_or(EX_DATAERR)
+}
+
+fn read_compiled(path: &str, bytes: &[u8]) -> Result<Chunk, i32> {
+    match loxc::version(bytes) {
+        Some(version) if version != loxc::VERSION => {
+            eprintln!("'{path}' is an unsupported .loxc version {version}, expect {}", loxc::VERSION);
+            return Err(EX_DATAERR);
+        }
+        _ => {}
+    }
+    loxc::read(bytes).map_err(|message| {
+        eprintln!("'{path}' is not valid bytecode: {message}");
+        EX_DATAERR
+    })
+}
+
+// print the tree of a file as `sexpr` or `json`
+pub fn run_ast(path: &str, format: &str) -> i32 {
+    let source = match read_source(path) {
+        Ok(source) => source,
+        Err(code) => return code,
+    };
+    reset_error();
+    let mut parser = Parser::new(Scanner::new(source).scan());
+    let stmts = parser.parse_program();
+    let Some(stmts) = stmts.filter(|_| !had_error()) else {
+        return EX_DATAERR;
+    };
+
+    match format {
+        "json" => println!("{}", dump::program_to_json(&parser, &stmts)),
+        _ => print!("{}", dump::program_to_sexpr(&parser.ast, &stmts)),
+    }
+    0
+}
+
+// `-` reads standard input, errors are reported and give the exit code
+fn read_source(path: &str) -> Result<String, i32> {
+    let bytes = read_bytes(path)?;
+    decode(path, bytes).map_err(|message| {
+        eprintln!("{message}");
+        EX_DATAERR
+    })
+}
+
+fn read_bytes(path: &str) -> Result<Vec<u8>, i32> {
+    let mut bytes = vec![];
+    let read = if path == "-" {
+        stdin().read_to_end(&mut bytes)
+    } else {
+        match File::open(path) {
+            Ok(mut file) => file.read_to_end(&mut bytes),
+            Err(err) => {
+                eprintln!("cannot open '{path}': {}", io_message(&err));
+                return Err(EX_NOINPUT);
+            }
+        }
+    };
+    if let Err(err) = read {
+        eprintln!("cannot read '{path}': {}", io_message(&err));
+        return Err(EX_NOINPUT);
+    }
+    Ok(bytes)
+}
+
+// the source as UTF-8, or where the first invalid byte is
+fn decode(path: &str, bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|err| {
+        let offset = err.utf8_error().valid_up_to();
+        let valid = &err.as_bytes()[..offset];
+        let line_start = valid.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        let line = valid.iter().filter(|b| **b == b'\n').count() + 1;
+        // `valid` is all UTF-8, so this cannot fail
+        let column = std::str::from_utf8(&valid[line_start..]).map_or(0, |s| s.chars().count()) + 1;
+        format!(
+            "'{path}' is not valid UTF-8: invalid byte 0x{:02x} at line {line}, column {column} (offset {offset})",
+            err.as_bytes()[offset]
+        )
+    })
+}
+
+// the message without the `(os error 2)` suffix
+fn io_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(i) => message[..i].to_owned(),
+        None => message,
     }
 }
+
+#[test]
+fn decode_test() {
+    assert_eq!(Ok("1 + é".to_owned()), decode("a.lox", "1 + é".as_bytes().to_vec()));
+    assert_eq!(
+        Err("'a.lox' is not valid UTF-8: invalid byte 0xff at line 2, column 3 (offset 7)".to_owned()),
+        decode("a.lox", b"1 +\n\"\xc3\xa9\xff\"".to_vec())
+    );
+}
+
+#[test]
+fn complete_word_test() {
+    let globals = vec!["value".to_owned(), "var_count".to_owned()];
+    assert_eq!(
+        (8, vec!["value".to_owned(), "var".to_owned(), "var_count".to_owned()]),
+        complete_word("print 1+va", 10, &globals)
+    );
+    assert_eq!((0, vec!["value".to_owned()]), complete_word("valu + 1", 4, &globals));
+    assert_eq!((3, vec![]), complete_word("a.v", 3, &globals));
+    assert_eq!((2, vec![]), complete_word("1 ", 2, &globals));
+    // the word starts after a multi-byte character
+    assert_eq!((9, vec![]), complete_word("print \"é", 9, &globals));
+    assert_eq!((3, vec!["value".to_owned()]), complete_word("é+valu", 7, &globals));
+}
+
+#[test]
+fn repl_state_test() {
+    let interpreter = Interpreter::default();
+    reset_error();
+    run_line(&interpreter, "var x = 1;");
+    run_line(&interpreter, "x += 2");
+    run_line(&interpreter, "x++; var y = x;");
+    run_line(&interpreter, "y *= 2");
+    assert!(!had_error() && !had_runtime_error());
+    assert_eq!(
+        vec![("x".to_owned(), Value::Number(4.0)), ("y".to_owned(), Value::Number(8.0))],
+        interpreter.globals()
+    );
+}
+
+#[test]
+fn non_ascii_test() {
+    let interpreter = Interpreter::default();
+    reset_error();
+    run_line(&interpreter, "var s = \"é\" + \"ü\";");
+    assert!(!had_error() && !had_runtime_error());
+    assert_eq!(vec![("s".to_owned(), Value::String("éü".to_owned()))], interpreter.globals());
+    // reported, not a panic
+    run_line(&interpreter, "1 + é;");
+    assert!(had_error());
+    assert!(Scanner::is_incomplete("print \"é"));
+    // columns count characters
+    let tokens = Scanner::new("\"é\" + x".to_owned()).scan();
+    assert_eq!(7, tokens[2].column);
+}
@@ -1,11 +1,12 @@
 use crate::{
     runner,
+    symbol::Symbol,
     token::{Token, TokenType, LiteralValue},
 };
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
 
-static KEYWORDS: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
+pub static KEYWORDS: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
     let mut m = HashMap::new();
     m.insert("and", TokenType::And);
     m.insert("class", TokenType::Class);
@@ -26,12 +27,31 @@ static KEYWORDS: Lazy<HashMap<&'static str, TokenType>> = Lazy::new(|| {
     m
 });
 
+// a comment and the index of the token following it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub line: u32,
+    pub token_index: usize,
+}
+
 pub struct Scanner {
     source: String,
     tokens: Vec<Token>,
+    comments: Vec<Comment>,
     start: u32,
     current: u32,
+    // where the last token ends
+    token_end: u32,
     line: u32,
+    // where the current line begins
+    line_start: u32,
+    // line and column of the token being scanned
+    start_line: u32,
+    start_column: u32,
+    // don't report errors, only record whether the source ended inside a string or comment
+    quiet: bool,
+    unterminated: bool,
 }
 
 impl Scanner {
@@ -39,15 +59,50 @@ impl Scanner {
         Self {
             source,
             tokens: vec![],
+            comments: vec![],
             start: 0,
             current: 0,
+            token_end: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            quiet: false,
+            unterminated: false,
+        }
+    }
+
+    pub fn scan(self) -> Vec<Token> {
+        self.scan_with_comments().0
+    }
+
+    // like scan, but keeps the comments for tools that reprint the source
+    pub fn scan_with_comments(mut self) -> (Vec<Token>, Vec<Comment>) {
+        self.scan_tokens();
+        (self.tokens, self.comments)
+    }
+
+    // whether more lines could complete the source: it ends inside a string or block comment,
+    // or has unclosed parens or braces, the prompt keeps reading then
+    pub fn is_incomplete(source: &str) -> bool {
+        let mut scanner = Scanner::new(source.to_owned());
+        scanner.quiet = true;
+        scanner.scan_tokens();
+
+        let mut depth = 0;
+        for token in &scanner.tokens {
+            match token.token_type {
+                TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+                _ => (),
+            }
         }
+        scanner.unterminated || depth > 0
     }
 
-    pub fn scan(mut self) -> Vec<Token> {
+    fn scan_tokens(&mut self) {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.start_token();
             match self.advance() {
                 // single character
                 '(' => self.add_token(TokenType::LeftParen),
@@ -56,19 +111,56 @@ impl Scanner {
                 '}' => self.add_token(TokenType::RightBrace),
                 ',' => self.add_token(TokenType::Comma),
                 '.' => self.add_token(TokenType::Dot),
-                '-' => self.add_token(TokenType::Minus),
-                '+' => self.add_token(TokenType::Plus),
                 ';' => self.add_token(TokenType::SemiColon),
-                '*' => self.add_token(TokenType::Star),
+                '%' => self.add_token(TokenType::Percent),
+                '?' => self.add_token(TokenType::Question),
+                ':' => self.add_token(TokenType::Colon),
+                '&' => self.add_token(TokenType::Ampersand),
+                '|' => self.add_token(TokenType::Pipe),
+                '^' => self.add_token(TokenType::Caret),
+                '~' => self.add_token(TokenType::Tilde),
                 // double
-                // ignore comment
+                '-' => {
+                    if self.is_match('-') {
+                        self.add_token(TokenType::MinusMinus);
+                    } else if self.is_match('=') {
+                        self.add_token(TokenType::MinusEqual);
+                    } else {
+                        self.add_token(TokenType::Minus);
+                    }
+                }
+                '+' => {
+                    if self.is_match('+') {
+                        self.add_token(TokenType::PlusPlus);
+                    } else if self.is_match('=') {
+                        self.add_token(TokenType::PlusEqual);
+                    } else {
+                        self.add_token(TokenType::Plus);
+                    }
+                }
+                '*' => {
+                    if self.is_match('*') {
+                        self.add_token(TokenType::StarStar);
+                    } else if self.is_match('=') {
+                        self.add_token(TokenType::StarEqual);
+                    } else {
+                        self.add_token(TokenType::Star);
+                    }
+                }
+                // ignore comment, unless `//` is integer division
                 '/' => {
-                    if self.is_match('/') {
+                    if self.is_floor_division() {
+                        self.advance();
+                        self.add_token(TokenType::SlashSlash);
+                    } else if self.is_match('/') {
                         while !self.is_at_end() && Some('\n') != self.peek() {
                             self.advance();
                         }
+                        self.add_comment(self.line);
                     } else if self.is_match('*') {
                         self.block_comment();
+                    } else if self.is_match('=') {
+                        self.add_token(TokenType::SlashEqual);
                     } else {
                         self.add_token(TokenType::Slash);
                     }
@@ -88,14 +180,18 @@ impl Scanner {
                     }
                 }
                 '<' => {
-                    if self.is_match('=') {
+                    if self.is_match('<') {
+                        self.add_token(TokenType::LessLess);
+                    } else if self.is_match('=') {
                         self.add_token(TokenType::LessEqual);
                     } else {
                         self.add_token(TokenType::Less);
                     }
                 }
                 '>' => {
-                    if self.is_match('=') {
+                    if self.is_match('>') {
+                        self.add_token(TokenType::GreaterGreater);
+                    } else if self.is_match('=') {
                         self.add_token(TokenType::GreaterEqual);
                     } else {
                         self.add_token(TokenType::Greater);
@@ -103,19 +199,38 @@ impl Scanner {
                 }
                 // ignore whitespace
                 ' ' | '\r' | '\t' => (),
-                '\n' => self.line += 1,
+                '\n' => self.newline(),
 
                 // literal
                 '"' => self.string(),
                 c if Self::is_digit(c) => self.digital(),
                 c if Self::is_alpha(c) => self.identifier(),
 
-                _ => runner::error(self.line, "Unexpected character."),
+                _ => self.error("Unexpected character."),
             }
         }
 
+        self.start_token();
         self.add_token(TokenType::Eof);
-        self.tokens
+    }
+
+    fn error(&self, message: &str) {
+        if !self.quiet {
+            runner::error(self.line, message);
+        }
+    }
+
+    fn start_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        // in characters, the offsets are in bytes
+        self.start_column = self.source[self.line_start as usize..self.current as usize].chars().count() as u32 + 1;
+    }
+
+    // call after consuming a '\n'
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn is_at_end(&self) -> bool {
@@ -123,24 +238,48 @@ impl Scanner {
     }
 
     fn peek(&self) -> Option<char> {
-        self.source.chars().nth(self.current as usize)
+        self.source[self.current as usize..].chars().next()
     }
 
     fn peek_next(&self) -> Option<char> {
-        self.source.chars().nth(self.current as usize + 1)
+        self.source[self.current as usize..].chars().nth(1)
     }
 
-    // find current character, and move current to next
+    // find current character, and move current to next, `current` is a byte offset
     fn advance(&mut self) -> char {
-        let r = self.source.chars().nth(self.current as usize).unwrap();
-        self.current += 1;
+        let r = self.peek().unwrap();
+        self.current += r.len_utf8() as u32;
         r
     }
 
+    // `//` is integer division written between two operands without spaces, as in `7//2`,
+    // and a comment otherwise, so `(a + 2) // trailing comment` keeps its meaning
+    fn is_floor_division(&self) -> bool {
+        let after_operand = match self.tokens.last() {
+            Some(token) if self.token_end == self.start => matches!(
+                token.token_type,
+                TokenType::Number
+                    | TokenType::String
+                    | TokenType::Identifier
+                    | TokenType::RightParen
+                    | TokenType::True
+                    | TokenType::False
+                    | TokenType::Nil
+                    | TokenType::This
+            ),
+            _ => false,
+        };
+        let before_operand = match self.peek_next() {
+            Some(c) => Self::is_alpah_numberic(c) || matches!(c, '(' | '"' | '-' | '!' | '~'),
+            None => false,
+        };
+        self.peek() == Some('/') && after_operand && before_operand
+    }
+
     fn is_match(&mut self, c: char) -> bool {
         match self.peek() {
             Some(v) if v == c => {
-                self.current += 1;
+                self.current += c.len_utf8() as u32;
                 true
             }
             _ => false,
@@ -148,11 +287,11 @@ impl Scanner {
     }
 
     fn is_digit(c: char) -> bool {
-        ('0'..='9').contains(&c)
+        c.is_ascii_digit()
     }
 
     fn is_alpha(c: char) -> bool {
-        ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || c == '_'
+        c.is_ascii_alphabetic() || c == '_'
     }
 
     fn is_alpah_numberic(c: char) -> bool {
@@ -182,11 +321,12 @@ impl Scanner {
     fn string(&mut self) {
         while !self.is_at_end() && self.peek() != Some('"') {
             if self.advance() == '\n' {
-                self.line += 1;
+                self.newline();
             }
         }
         if self.is_at_end() {
-            runner::error(self.line, "string unclosed.");
+            self.unterminated = true;
+            self.error("string unclosed.");
             return;
         }
         // move to the closed "
@@ -195,8 +335,8 @@ impl Scanner {
         let v = self
             .source
             .get((self.start as usize + 1)..(self.current as usize - 1))
-            .unwrap()
-            .to_owned();
+            .unwrap();
+        let v = Symbol::intern(v);
         self.add_token_with_literal(TokenType::String, Some(LiteralValue::String(v)));
     }
 
@@ -220,19 +360,34 @@ impl Scanner {
     }
 
     fn block_comment(&mut self) {
-        while !self.is_at_end() && !(self.peek() == Some('*') && self.peek_next() == Some('/')) {
-            if self.peek() == Some('\n') {
-                self.line += 1;
+        let line = self.line;
+        while !(self.is_at_end() || self.peek() == Some('*') && self.peek_next() == Some('/')) {
+            if self.advance() == '\n' {
+                self.newline();
             }
-            self.advance();
         }
         if self.is_at_end() {
-            runner::error(self.line, "Block comment unclosed.");
+            self.unterminated = true;
+            self.error("Block comment unclosed.");
             return;
         }
         // move to */
         self.advance();
         self.advance();
+        self.add_comment(line);
+    }
+
+    fn add_comment(&mut self, line: u32) {
+        let text = self
+            .source
+            .get((self.start as usize)..(self.current as usize))
+            .unwrap()
+            .to_owned();
+        self.comments.push(Comment {
+            text,
+            line,
+            token_index: self.tokens.len(),
+        });
     }
 
     fn add_token(&mut self, token_type: TokenType) {
@@ -243,8 +398,87 @@ impl Scanner {
         let lexeme = self
             .source
             .get((self.start as usize)..(self.current as usize))
-            .unwrap()
-            .to_owned();
-        self.tokens.push(Token::new(token_type, lexeme, literal, self.line));
+            .unwrap();
+        self.token_end = self.current;
+        self.tokens.push(Token::new(
+            token_type,
+            lexeme,
+            literal,
+            self.start_line,
+            self.start_column,
+        ));
     }
 }
+
+#[cfg(test)]
+fn types(source: &str) -> Vec<TokenType> {
+    Scanner::new(source.to_owned())
+        .scan()
+        .into_iter()
+        .map(|t| t.token_type)
+        .collect()
+}
+
+#[test]
+fn slash_slash_test() {
+    assert_eq!(
+        vec![TokenType::Number, TokenType::SlashSlash, TokenType::Number, TokenType::Eof],
+        types("7//2")
+    );
+    assert_eq!(
+        vec![
+            TokenType::RightParen,
+            TokenType::SlashSlash,
+            TokenType::Minus,
+            TokenType::Identifier,
+            TokenType::Eof
+        ],
+        types(")//-a")
+    );
+    // with spaces around it, or without an operand on both sides, `//` is a comment
+    assert_eq!(vec![TokenType::SemiColon, TokenType::Eof], types("; // 7 // 2"));
+    assert_eq!(vec![TokenType::SemiColon, TokenType::Eof], types(";//7"));
+    assert_eq!(vec![TokenType::Number, TokenType::Eof], types("7// 2"));
+    assert_eq!(vec![TokenType::Number, TokenType::Eof], types("7 // 2"));
+    assert_eq!(
+        vec![TokenType::LeftParen, TokenType::Number, TokenType::RightParen, TokenType::SemiColon, TokenType::Eof],
+        types("(1) // three\n;")
+    );
+    assert_eq!(vec![TokenType::Identifier, TokenType::Eof], types("a // trailing comment"));
+    assert_eq!(
+        vec![TokenType::Number, TokenType::StarStar, TokenType::Number, TokenType::Eof],
+        types("2 ** 3")
+    );
+}
+
+#[test]
+fn compound_assignment_test() {
+    assert_eq!(
+        vec![TokenType::Identifier, TokenType::PlusPlus, TokenType::SemiColon, TokenType::Eof],
+        types("x++;")
+    );
+    assert_eq!(
+        vec![
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+            TokenType::MinusMinus,
+            TokenType::Minus,
+            TokenType::Eof
+        ],
+        types("+= -= *= /= ---")
+    );
+}
+
+#[test]
+fn incomplete_test() {
+    assert!(!Scanner::is_incomplete("1 + 2\n"));
+    assert!(Scanner::is_incomplete("(1 +\n"));
+    assert!(Scanner::is_incomplete("{ (1) \n"));
+    assert!(Scanner::is_incomplete("\"abc\n"));
+    assert!(Scanner::is_incomplete("1 /* note\n"));
+    // too many closing parens can't be fixed by reading more
+    assert!(!Scanner::is_incomplete("1)\n"));
+    assert!(!runner::had_error());
+}
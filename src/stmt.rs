@@ -0,0 +1,50 @@
+#![allow(dead_code)]
+use crate::{ast::Ast, expr::ExprId, token::Token};
+use lox_ast_derive::ast;
+
+#[ast(arena = Ast, children(Expr = crate::expr))]
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Expression { expression: ExprId },
+    Print { expression: ExprId },
+    Var { name: Token, initializer: Option<ExprId> },
+}
+
+#[test]
+fn walk_and_fold_test() {
+    use crate::{
+        dump::program_to_sexpr,
+        expr::{self, ExprVisitorMut, GroupingExpr, LiteralExpr},
+        parser::Parser,
+        scanner::Scanner,
+    };
+
+    // counts the literals and removes groupings, through the statements
+    struct Pass {
+        literals: usize,
+    }
+    impl ExprVisitorMut<()> for Pass {
+        fn visit_literal(&mut self, _: &Ast, _: ExprId, _: &LiteralExpr) {
+            self.literals += 1;
+        }
+    }
+    impl StmtVisitorMut<()> for Pass {}
+    impl expr::Fold for Pass {
+        fn fold_grouping(&mut self, ast: &Ast, out: &mut Ast, expr: &GroupingExpr) -> ExprId {
+            self.fold_expr(ast, out, expr.expression)
+        }
+    }
+    impl Fold for Pass {}
+
+    let mut parser = Parser::new(Scanner::new("var a = (1);\nprint (a + 2);\n(3);".to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    let mut pass = Pass { literals: 0 };
+    for &stmt in &stmts {
+        pass.visit_stmt(&parser.ast, stmt);
+    }
+    assert_eq!(3, pass.literals);
+
+    let mut out = Ast::new();
+    let stmts = stmts.iter().map(|&stmt| pass.fold_stmt(&parser.ast, &mut out, stmt)).collect::<Vec<_>>();
+    assert_eq!("(var a 1)\n(print (+ a 2))\n(expression 3)\n", program_to_sexpr(&out, &stmts));
+}
@@ -0,0 +1,91 @@
+// interned strings: every distinct string is stored once for the whole process and a
+// `Symbol` points at it, so symbols are copied, compared and hashed as a pointer and read
+// without taking the lock, which only `intern` needs.
+//
+// The strings are leaked, the memory grows with the distinct identifiers and string
+// literals ever scanned, which stays small for programs but not for a prompt fed an
+// endless stream of new strings.
+
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    ptr,
+    sync::Mutex,
+};
+
+#[derive(Clone, Copy)]
+pub struct Symbol(&'static str);
+
+static INTERNER: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(Mutex::default);
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(s) = interner.get(s) {
+            return Symbol(s);
+        }
+        let s: &'static str = Box::leak(s.into());
+        interner.insert(s);
+        Symbol(s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        self.0
+    }
+}
+
+// the same string is always the same symbol, the address tells them apart
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ptr().hash(state)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Symbol::intern(s)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[test]
+fn intern_test() {
+    let a = Symbol::intern("symbol_test_a");
+    assert_eq!(a, Symbol::intern(&String::from("symbol_test_a")));
+    assert_ne!(a, Symbol::intern("symbol_test_b"));
+    assert_eq!("symbol_test_a", a.as_str());
+    assert_eq!(13, a.len());
+    assert_eq!("\"symbol_test_a\"", format!("{a:?}"));
+    // the interned string itself, not a copy
+    assert!(ptr::eq(a.as_str(), Symbol::intern("symbol_test_a").as_str()));
+}
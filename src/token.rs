@@ -1,3 +1,7 @@
+use std::fmt::Display;
+
+use crate::symbol::Symbol;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // single character
@@ -12,6 +16,13 @@ pub enum TokenType {
     SemiColon,
     Slash,
     Star,
+    Percent,
+    Question,
+    Colon,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     // on or two character
     Equal,
@@ -22,6 +33,16 @@ pub enum TokenType {
     LessEqual,
     Greater,
     GreaterEqual,
+    StarStar,
+    SlashSlash,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PlusPlus,
+    MinusMinus,
+    LessLess,
+    GreaterGreater,
 
     Identifier,
     // literal, literal need to save literal
@@ -51,7 +72,7 @@ pub enum TokenType {
 
 #[derive(Debug, Clone)]
 pub enum LiteralValue {
-    String(String),
+    String(Symbol),
     Nubmer(f64),
     Bool(bool),
     Nil,
@@ -61,23 +82,62 @@ pub enum LiteralValue {
 #[allow(unused)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Option<LiteralValue>,
-    // which line this token in
+    // which line this token starts in
     pub line: u32,
+    // column of the first character, starts from 1, 0 if unknown
+    pub column: u32,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Option<LiteralValue>, line: u32) -> Self {
+    pub fn new(token_type: TokenType, lexeme: &str, literal: Option<LiteralValue>, line: u32, column: u32) -> Self {
         Self {
             token_type,
-            lexeme,
+            lexeme: Symbol::intern(lexeme),
             literal,
             line,
+            column,
+        }
+    }
+
+    pub fn new_not_literal(token_type: TokenType, lexeme: &str, line: u32) -> Self {
+        Self {
+            token_type,
+            lexeme: Symbol::intern(lexeme),
+            literal: None,
+            line,
+            column: 0,
+        }
+    }
+
+    // where the last character of this token is
+    pub fn end(&self) -> (u32, u32) {
+        match self.lexeme.rfind('\n') {
+            Some(i) => (
+                self.line + self.lexeme.matches('\n').count() as u32,
+                self.lexeme[i + 1..].chars().count() as u32,
+            ),
+            None => (self.line, self.column + self.lexeme.chars().count().max(1) as u32 - 1),
         }
     }
+}
+
+// source range of a node, both ends inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
 
-    pub fn new_not_literal(token_type: TokenType, lexeme: String, line: u32) -> Self {
-        Self { token_type, lexeme, literal: None, line }
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.start_line, self.start_column, self.end_line, self.end_column
+        )
     }
 }
@@ -0,0 +1,150 @@
+// the values of the VM: nil, booleans and numbers inline, objects as handles into the heap.
+// With the `nan-boxing` feature a value is a single u64, a number is its own bits and the
+// others hide in the payload of a quiet NaN, otherwise it is a plain enum. Both have the
+// same API, `unpack` gives the value to match on.
+
+use std::fmt;
+
+use crate::gc::ObjRef;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unpacked {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Obj(ObjRef),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Clone, Copy)]
+pub struct VmValue(Unpacked);
+
+#[cfg(not(feature = "nan-boxing"))]
+impl VmValue {
+    pub const NIL: VmValue = VmValue(Unpacked::Nil);
+
+    pub fn bool(v: bool) -> Self {
+        VmValue(Unpacked::Bool(v))
+    }
+
+    pub fn number(v: f64) -> Self {
+        VmValue(Unpacked::Number(v))
+    }
+
+    pub fn obj(obj: ObjRef) -> Self {
+        VmValue(Unpacked::Obj(obj))
+    }
+
+    pub fn unpack(self) -> Unpacked {
+        self.0
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        match self.0 {
+            Unpacked::Number(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn is_falsey(self) -> bool {
+        matches!(self.0, Unpacked::Nil | Unpacked::Bool(false))
+    }
+}
+
+// the bits every boxed value has, no number has them all once NaNs are canonical
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+// set for objects, whose handle is the low 32 bits
+#[cfg(feature = "nan-boxing")]
+const SIGN: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct VmValue(u64);
+
+#[cfg(feature = "nan-boxing")]
+impl VmValue {
+    pub const NIL: VmValue = VmValue(QNAN | TAG_NIL);
+
+    pub fn bool(v: bool) -> Self {
+        VmValue(QNAN | if v { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    // any NaN becomes the canonical one, which is not a boxed value
+    pub fn number(v: f64) -> Self {
+        VmValue(if v.is_nan() { f64::NAN.to_bits() } else { v.to_bits() })
+    }
+
+    pub fn obj(obj: ObjRef) -> Self {
+        VmValue(SIGN | QNAN | obj.0 as u64)
+    }
+
+    pub fn unpack(self) -> Unpacked {
+        if let Some(v) = self.as_number() {
+            return Unpacked::Number(v);
+        }
+        if self.0 & SIGN != 0 {
+            return Unpacked::Obj(ObjRef(self.0 as u32));
+        }
+        match self.0 & 3 {
+            TAG_NIL => Unpacked::Nil,
+            TAG_FALSE => Unpacked::Bool(false),
+            _ => Unpacked::Bool(true),
+        }
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        (self.0 & QNAN != QNAN).then_some(f64::from_bits(self.0))
+    }
+
+    pub fn is_falsey(self) -> bool {
+        self.0 == QNAN | TAG_NIL || self.0 == QNAN | TAG_FALSE
+    }
+}
+
+impl fmt::Debug for VmValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.unpack().fmt(f)
+    }
+}
+
+#[test]
+fn pack_test() {
+    let values = [
+        Unpacked::Nil,
+        Unpacked::Bool(false),
+        Unpacked::Bool(true),
+        Unpacked::Number(0.0),
+        Unpacked::Number(-0.0),
+        Unpacked::Number(1.5),
+        Unpacked::Number(f64::INFINITY),
+        Unpacked::Number(f64::NEG_INFINITY),
+        Unpacked::Number(f64::MIN_POSITIVE),
+        Unpacked::Obj(ObjRef(0)),
+        Unpacked::Obj(ObjRef(u32::MAX)),
+    ];
+    for unpacked in values {
+        let value = match unpacked {
+            Unpacked::Nil => VmValue::NIL,
+            Unpacked::Bool(v) => VmValue::bool(v),
+            Unpacked::Number(v) => VmValue::number(v),
+            Unpacked::Obj(obj) => VmValue::obj(obj),
+        };
+        // through Debug, so 0 and -0 must stay apart
+        assert_eq!(format!("{unpacked:?}"), format!("{value:?}"));
+        assert_eq!(matches!(unpacked, Unpacked::Nil | Unpacked::Bool(false)), value.is_falsey());
+    }
+
+    for nan in [f64::NAN, -f64::NAN, f64::from_bits(0x7ffc_0000_0000_0001), f64::from_bits(u64::MAX)] {
+        assert!(VmValue::number(nan).as_number().unwrap().is_nan());
+    }
+
+    let size = if cfg!(feature = "nan-boxing") { 8 } else { 16 };
+    assert_eq!(size, std::mem::size_of::<VmValue>());
+}
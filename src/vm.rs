@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    gc::{Heap, Obj},
+    interpreter::{self, Value},
+    symbol::Symbol,
+    token::TokenType,
+    value::{Unpacked, VmValue},
+};
+
+#[derive(Debug, PartialEq)]
+pub struct VmErr {
+    pub line: u32,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VmOptions {
+    // print the stack and the instruction before running it, as clox's DEBUG_TRACE_EXECUTION
+    pub trace: bool,
+    pub stress_gc: bool,
+    pub gc_growth: f64,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        VmOptions {
+            trace: false,
+            stress_gc: false,
+            gc_growth: 2.0,
+        }
+    }
+}
+
+// runs chunks on a value stack, the globals stay for the next chunk
+pub struct Vm {
+    trace: bool,
+    heap: Heap,
+    stack: Vec<VmValue>,
+    globals: HashMap<Symbol, VmValue>,
+    // the constants of the running chunk, with their strings on the heap
+    constants: Vec<VmValue>,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new(VmOptions::default())
+    }
+}
+
+impl Vm {
+    pub fn new(options: VmOptions) -> Self {
+        Vm {
+            trace: options.trace,
+            heap: Heap::new(options.gc_growth, options.stress_gc),
+            stack: vec![],
+            globals: HashMap::new(),
+            constants: vec![],
+        }
+    }
+
+    pub fn run(&mut self, chunk: &Chunk) -> Result<(), VmErr> {
+        self.stack.clear();
+        self.constants.clear();
+        for constant in &chunk.constants {
+            let constant = self.vm_value(constant.clone());
+            self.constants.push(constant);
+        }
+        // the string constants as symbols, so a global is found without hashing its name
+        let names: Vec<Option<Symbol>> = chunk
+            .constants
+            .iter()
+            .map(|constant| match constant {
+                Value::String(s) => Some(Symbol::intern(s)),
+                _ => None,
+            })
+            .collect();
+        let name = |operand: usize| names[chunk.read_u16(operand) as usize].expect("name is not a string");
+
+        let mut ip = 0;
+        loop {
+            let start = ip;
+            if self.trace {
+                let stack: String = self.stack.iter().map(|v| format!("[ {} ]", self.to_value(*v))).collect();
+                println!("          {stack}");
+                println!("{}", chunk.disassemble_instruction(ip).0);
+            }
+            let op = OpCode::try_from(chunk.code[ip]).expect("invalid opcode");
+            ip += 1;
+            let error = |message: &str| VmErr {
+                line: chunk.lines[start],
+                message: message.to_owned(),
+            };
+
+            match op {
+                OpCode::Constant => {
+                    self.stack.push(self.constants[chunk.read_u16(ip) as usize]);
+                    ip += 2;
+                }
+                OpCode::Nil => self.stack.push(VmValue::NIL),
+                OpCode::True => self.stack.push(VmValue::bool(true)),
+                OpCode::False => self.stack.push(VmValue::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::DefineGlobal => {
+                    let name = name(ip);
+                    ip += 2;
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::GetGlobal => {
+                    let name = name(ip);
+                    ip += 2;
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(*value),
+                        None => return Err(error(&format!("Undefined variable '{name}'."))),
+                    }
+                }
+                OpCode::SetGlobal => {
+                    let name = name(ip);
+                    ip += 2;
+                    let value = *self.stack.last().expect("stack underflow");
+                    match self.globals.get_mut(&name) {
+                        Some(slot) => *slot = value,
+                        None => return Err(error(&format!("Undefined variable '{name}'."))),
+                    }
+                }
+                OpCode::Not => {
+                    let right = self.pop();
+                    self.stack.push(VmValue::bool(right.is_falsey()));
+                }
+                OpCode::Negate | OpCode::BitNot => {
+                    let right = self.pop();
+                    if let (OpCode::Negate, Some(v)) = (op, right.as_number()) {
+                        self.stack.push(VmValue::number(-v));
+                        continue;
+                    }
+                    let operator = if op == OpCode::Negate { TokenType::Minus } else { TokenType::Tilde };
+                    let result = interpreter::unary(&operator, &self.to_value(right)).map_err(error)?;
+                    let result = self.vm_value(result);
+                    self.stack.push(result);
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    println!("{}", self.to_value(value));
+                }
+                OpCode::Jump => {
+                    ip += 2 + chunk.read_u16(ip) as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = chunk.read_u16(ip) as usize;
+                    ip += 2;
+                    if self.pop().is_falsey() {
+                        ip += offset;
+                    }
+                }
+                OpCode::Return => return Ok(()),
+                _ => {
+                    let operator = match op {
+                        OpCode::Add => TokenType::Plus,
+                        OpCode::Subtract => TokenType::Minus,
+                        OpCode::Multiply => TokenType::Star,
+                        OpCode::Divide => TokenType::Slash,
+                        OpCode::Modulo => TokenType::Percent,
+                        OpCode::FloorDivide => TokenType::SlashSlash,
+                        OpCode::Power => TokenType::StarStar,
+                        OpCode::BitAnd => TokenType::Ampersand,
+                        OpCode::BitOr => TokenType::Pipe,
+                        OpCode::BitXor => TokenType::Caret,
+                        OpCode::ShiftLeft => TokenType::LessLess,
+                        OpCode::ShiftRight => TokenType::GreaterGreater,
+                        OpCode::Equal => TokenType::EqualEqual,
+                        OpCode::NotEqual => TokenType::BangEqual,
+                        OpCode::Less => TokenType::Less,
+                        OpCode::LessEqual => TokenType::LessEqual,
+                        OpCode::Greater => TokenType::Greater,
+                        OpCode::GreaterEqual => TokenType::GreaterEqual,
+                        _ => unreachable!("not a binary opcode {op:?}"),
+                    };
+                    let right = self.pop();
+                    let left = self.pop();
+                    if let Some(result) = self.binary(op, left, right) {
+                        self.stack.push(result);
+                        continue;
+                    }
+                    // the rest is checked or fails, no string gets here but to report an error
+                    let result = interpreter::binary(&operator, self.to_value(left), self.to_value(right));
+                    let result = self.vm_value(result.map_err(error)?);
+                    self.stack.push(result);
+                }
+            }
+        }
+    }
+
+    // the global variables sorted by name
+    pub fn globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self.globals.iter().map(|(k, v)| (k.to_string(), self.to_value(*v))).collect();
+        globals.sort_by(|a, b| a.0.cmp(&b.0));
+        globals
+    }
+
+    // the common operators, on VM values so strings are not copied out of the heap
+    fn binary(&mut self, op: OpCode, left: VmValue, right: VmValue) -> Option<VmValue> {
+        match (op, left.unpack(), right.unpack()) {
+            (_, Unpacked::Number(l), Unpacked::Number(r)) => Self::arithmetic(op, l, r),
+            (OpCode::Equal, ..) => Some(VmValue::bool(self.equal(left, right))),
+            (OpCode::NotEqual, ..) => Some(VmValue::bool(!self.equal(left, right))),
+            (OpCode::Add, Unpacked::Obj(l), Unpacked::Obj(r)) => match (self.heap.get(l), self.heap.get(r)) {
+                (Obj::String(l), Obj::String(r)) => {
+                    let s = [l.as_str(), r.as_str()].concat();
+                    Some(self.alloc_string(s))
+                }
+                #[cfg(test)]
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn equal(&self, left: VmValue, right: VmValue) -> bool {
+        match (left.unpack(), right.unpack()) {
+            (Unpacked::Obj(l), Unpacked::Obj(r)) => {
+                l == r
+                    || matches!((self.heap.get(l), self.heap.get(r)), (Obj::String(l), Obj::String(r)) if l == r)
+            }
+            (l, r) => l == r,
+        }
+    }
+
+    // the operators on two numbers which cannot fail, without going through `Value`
+    fn arithmetic(op: OpCode, l: f64, r: f64) -> Option<VmValue> {
+        Some(match op {
+            OpCode::Add => VmValue::number(l + r),
+            OpCode::Subtract => VmValue::number(l - r),
+            OpCode::Multiply => VmValue::number(l * r),
+            OpCode::Equal => VmValue::bool(l == r),
+            OpCode::NotEqual => VmValue::bool(l != r),
+            OpCode::Less => VmValue::bool(l < r),
+            OpCode::LessEqual => VmValue::bool(l <= r),
+            OpCode::Greater => VmValue::bool(l > r),
+            OpCode::GreaterEqual => VmValue::bool(l >= r),
+            _ => return None,
+        })
+    }
+
+    // mark the roots, then free what they do not reach
+    pub fn collect_garbage(&mut self) {
+        for value in self.stack.iter().chain(self.globals.values()).chain(&self.constants) {
+            self.heap.mark_value(*value);
+        }
+        self.heap.collect();
+    }
+
+    // the operators work on interpreter values, so both backends share them
+    fn to_value(&self, value: VmValue) -> Value {
+        match value.unpack() {
+            Unpacked::Nil => Value::Nil,
+            Unpacked::Bool(v) => Value::Bool(v),
+            Unpacked::Number(v) => Value::Number(v),
+            Unpacked::Obj(obj) => match self.heap.get(obj) {
+                Obj::String(s) => Value::String(s.to_owned()),
+                #[cfg(test)]
+                Obj::List(_) => unreachable!("lists are only in the heap's tests"),
+            },
+        }
+    }
+
+    // may collect, so the values in use must be on the stack or elsewhere in the roots
+    fn vm_value(&mut self, value: Value) -> VmValue {
+        match value {
+            Value::Nil => VmValue::NIL,
+            Value::Bool(v) => VmValue::bool(v),
+            Value::Number(v) => VmValue::number(v),
+            Value::String(s) => self.alloc_string(s),
+        }
+    }
+
+    fn alloc_string(&mut self, s: String) -> VmValue {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        VmValue::obj(self.heap.alloc(Obj::String(s)))
+    }
+
+    fn pop(&mut self) -> VmValue {
+        self.stack.pop().expect("stack underflow")
+    }
+}
+
+// both backends must end with the same globals, or fail the same way
+#[cfg(test)]
+fn run_both(ast: &crate::ast::Ast, stmts: &[crate::stmt::StmtId]) -> (String, String) {
+    use crate::{compiler, interpreter::Interpreter};
+
+    let interpreter = Interpreter::default();
+    let tree = match interpreter.execute(ast, stmts) {
+        Ok(()) => format!("{:?}", interpreter.globals()),
+        Err(err) => format!("[line {}] {}", err.token.line, err.message),
+    };
+    // collecting before every allocation finds the values missing from the roots
+    let mut vm = Vm::new(VmOptions {
+        stress_gc: true,
+        ..VmOptions::default()
+    });
+    let chunk = compiler::compile(ast, stmts).unwrap();
+    let bytecode = match vm.run(&chunk) {
+        Ok(()) => format!("{:?}", vm.globals()),
+        Err(err) => format!("[line {}] {}", err.line, err.message),
+    };
+
+    (tree, bytecode)
+}
+
+#[test]
+fn backends_test() {
+    use crate::{parser::Parser, scanner::Scanner};
+
+    let programs = [
+        "var a = -2 ** 3; var b = 2 ** 3 ** 2; var c = 7 % 3; var d = -7//2;",
+        "var a = 1 > 2 ? \"a\" : \"a\" + \"b\"; var b = nil == false; var c = !nil;",
+        "var a = 5 | 3 ^ 1 & 7; var b = ~5; var c = 1 << 3 >> 2 ^ -4;",
+        "var a = 1; var b; b = a = a + 2; a = (a, b, 10);",
+        "var a = 1; a += 2; a *= a++; var b = --a; var c = a--; var d = \"x\"; d += d;",
+        "var a = \"a\" + \"b\" == \"ab\"; var b = \"a\" != \"a\"; var c = nil == \"\"; var d = !\"\" == -1;",
+        "var a = true ? 1 : 1 / 0; var b = false ? 1 / 0 : 2;",
+        "var a = 1;\nvar b = a +\n \"x\";",
+        "var a = 1//0;",
+        "var a = 1.5 & 1;",
+        "var a = ~0.5;",
+        "var a = 1 << 64;",
+        "var a = -\"x\";",
+        "var a = 1;\n\nb = a;",
+        "print c;",
+    ];
+    for program in programs {
+        let mut parser = Parser::new(Scanner::new(program.to_owned()).scan());
+        let stmts = parser.parse_program().unwrap();
+        let (tree, bytecode) = run_both(&parser.ast, &stmts);
+        assert_eq!(tree, bytecode, "{program}");
+    }
+}
+
+#[test]
+fn random_backends_test() {
+    use crate::{
+        ast::Ast,
+        exercise::ch5::{random_expr, AstVisitor, Rng},
+        stmt::{Stmt, VarStmt},
+        token::Token,
+    };
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..2000 {
+        let mut ast = Ast::new();
+        let expr = random_expr(&mut rng, &mut ast, 5);
+        let stmt = ast.add_stmt(Stmt::Var(VarStmt {
+            name: Token::new_not_literal(TokenType::Identifier, "result", 1),
+            initializer: Some(expr),
+        }));
+        let (tree, bytecode) = run_both(&ast, &[stmt]);
+        // NaN results print the same through Debug
+        assert_eq!(tree, bytecode, "{}", expr.visit(&ast, &AstVisitor));
+    }
+}
+
+#[test]
+fn gc_test() {
+    use crate::{compiler, parser::Parser, scanner::Scanner};
+
+    let source = "var a = \"x\"; a = a + \"y\"; a = a + \"z\"; var b = a + a; b = a;";
+    let mut parser = Parser::new(Scanner::new(source.to_owned()).scan());
+    let stmts = parser.parse_program().unwrap();
+    let chunk = compiler::compile(&parser.ast, &stmts).unwrap();
+    let mut vm = Vm::default();
+    vm.run(&chunk).unwrap();
+    vm.collect_garbage();
+    // the five string constants and "xyz" stay, "xy" and "xyzxyz" are freed
+    assert_eq!(6, vm.heap.len());
+    assert_eq!(
+        vec![("a".to_owned(), Value::String("xyz".to_owned())), ("b".to_owned(), Value::String("xyz".to_owned()))],
+        vm.globals()
+    );
+}